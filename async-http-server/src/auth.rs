@@ -0,0 +1,241 @@
+use std::{collections::HashMap, fs, path::PathBuf};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ring::digest;
+use serde::Deserialize;
+
+use crate::{blocking::BlockingPool, http::Response, site};
+
+/// One protected part of a site, as set up in the config file. A request
+/// under `prefix` needs either a user name and password from the `htpasswd`
+/// file, sent with Basic auth, or one of the `tokens`, sent as a bearer token.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Protect this path and everything under it. It is normalised the same
+    /// way request paths are, so `/%61dmin/` means `/admin`.
+    pub prefix: String,
+
+    /// Shown by browsers when they prompt for a password.
+    #[serde(default = "default_realm")]
+    pub realm: String,
+
+    /// A file of `user:hash` lines, as made by `htpasswd -B`.
+    #[serde(default)]
+    pub htpasswd: Option<PathBuf>,
+
+    #[serde(default)]
+    pub tokens: Vec<String>,
+}
+
+fn default_realm() -> String {
+    String::from("Restricted")
+}
+
+/// Guards every request under a path prefix, answering `401` unless the
+/// request carries good credentials.
+pub struct Protection {
+    prefix: String,
+    realm: String,
+
+    /// User names to their bcrypt hashes.
+    users: HashMap<String, String>,
+
+    /// Any one of `users`' hashes, to check unknown users against.
+    dummy_hash: String,
+
+    /// SHA-256 digests of the tokens, so checking one takes the same time
+    /// however much of it matches.
+    tokens: Vec<digest::Digest>,
+
+    /// Checking a bcrypt hash is deliberately slow, far too slow to do on a
+    /// worker thread.
+    blocking: BlockingPool,
+}
+
+impl Protection {
+    pub fn from_config(
+        config: &AuthConfig,
+        blocking: BlockingPool,
+    ) -> Result<Protection, String> {
+        // Requests are matched after normalising, so the prefix has to be
+        // too, or a prefix spelled differently would never match anything.
+        let prefix = config
+            .prefix
+            .starts_with('/')
+            .then(|| site::normalize(&config.prefix))
+            .flatten()
+            .ok_or_else(|| format!("bad auth prefix: {}", config.prefix))?;
+
+        let users = match &config.htpasswd {
+            Some(path) => {
+                let source = fs::read_to_string(path)
+                    .map_err(|error| format!("{}: {error}", path.display()))?;
+                parse_htpasswd(&source)
+                    .map_err(|error| format!("{}: {error}", path.display()))?
+            }
+            None => HashMap::new(),
+        };
+
+        if users.is_empty() && config.tokens.is_empty() {
+            return Err(format!(
+                "nobody could ever get into {}: no users and no tokens",
+                config.prefix
+            ));
+        }
+
+        Ok(Protection {
+            prefix: prefix.trim_end_matches('/').to_string(),
+            realm: config.realm.clone(),
+            dummy_hash: users.values().next().cloned().unwrap_or_default(),
+            users,
+            tokens: config.tokens.iter().map(|token| sha256(token)).collect(),
+            blocking,
+        })
+    }
+
+    /// Whether `path` is at or under the prefix: `/admin` covers `/admin`
+    /// and `/admin/users`, but not `/administrator`. The path needs to be
+    /// decoded and normalised first, or `/%61dmin` would get past.
+    pub fn covers(&self, path: &str) -> bool {
+        match path.strip_prefix(&self.prefix) {
+            Some(rest) => rest.is_empty() || rest.starts_with('/'),
+            None => false,
+        }
+    }
+
+    /// How long the prefix is, so the most specific protection can win.
+    pub(crate) fn specificity(&self) -> usize {
+        self.prefix.len()
+    }
+
+    /// Check the request's `Authorization` header, and hand back the `401`
+    /// to send if it is missing or wrong.
+    pub async fn check(
+        &self,
+        authorization: Option<&str>,
+    ) -> Result<(), Response> {
+        let Some((scheme, credentials)) =
+            authorization.and_then(|value| value.trim().split_once(' '))
+        else {
+            return Err(self.challenge(false));
+        };
+
+        let credentials = credentials.trim();
+        if scheme.eq_ignore_ascii_case("Bearer") && !self.tokens.is_empty() {
+            let token = sha256(credentials);
+            let known = self
+                .tokens
+                .iter()
+                .any(|known| known.as_ref() == token.as_ref());
+
+            return if known {
+                Ok(())
+            } else {
+                Err(self.challenge(true))
+            };
+        }
+
+        if scheme.eq_ignore_ascii_case("Basic")
+            && !self.users.is_empty()
+            && self.check_basic(credentials).await
+        {
+            return Ok(());
+        }
+
+        Err(self.challenge(false))
+    }
+
+    async fn check_basic(&self, credentials: &str) -> bool {
+        let Some(decoded) = STANDARD
+            .decode(credentials)
+            .ok()
+            .and_then(|decoded| String::from_utf8(decoded).ok())
+        else {
+            return false;
+        };
+
+        let Some((user, password)) = decoded.split_once(':') else {
+            return false;
+        };
+
+        // Check an unknown user's password anyway, against a hash of the
+        // same cost, so how long the answer takes does not give away which
+        // user names exist.
+        let (hash, known) = match self.users.get(user) {
+            Some(hash) => (hash.clone(), true),
+            None => (self.dummy_hash.clone(), false),
+        };
+
+        let password = password.to_string();
+        let verified = self
+            .blocking
+            .spawn(move || bcrypt::verify(password, &hash))
+            .await;
+
+        known && matches!(verified, Ok(Ok(true)))
+    }
+
+    /// A `401` naming every scheme which could get the client in.
+    fn challenge(&self, bad_token: bool) -> Response {
+        let realm = self.realm.replace(['\\', '"'], "");
+        let mut response = Response::new(401).with_body("Unauthorized\n");
+
+        if !self.users.is_empty() {
+            response = response.with_header(
+                "WWW-Authenticate",
+                format!("Basic realm=\"{realm}\", charset=\"UTF-8\""),
+            );
+        }
+
+        if !self.tokens.is_empty() {
+            let error = if bad_token {
+                ", error=\"invalid_token\""
+            } else {
+                ""
+            };
+            response = response.with_header(
+                "WWW-Authenticate",
+                format!("Bearer realm=\"{realm}\"{error}"),
+            );
+        }
+
+        response
+    }
+}
+
+/// Parse `user:hash` lines, skipping blank lines and `#` comments. Only bcrypt
+/// hashes are accepted: the other formats `htpasswd` can write are too weak
+/// to bother with.
+fn parse_htpasswd(source: &str) -> Result<HashMap<String, String>, String> {
+    let mut users = HashMap::new();
+    for (number, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let number = number + 1;
+        let (user, hash) = line
+            .split_once(':')
+            .ok_or_else(|| format!("line {number}: expected user:hash"))?;
+
+        let bcrypt = ["$2a$", "$2b$", "$2y$"]
+            .iter()
+            .any(|prefix| hash.starts_with(prefix));
+        if !bcrypt {
+            return Err(format!(
+                "line {number}: {user} does not have a bcrypt hash \
+                 (make one with `htpasswd -B`)"
+            ));
+        }
+
+        users.insert(user.to_string(), hash.to_string());
+    }
+
+    Ok(users)
+}
+
+fn sha256(text: &str) -> digest::Digest {
+    digest::digest(&digest::SHA256, text.as_bytes())
+}
@@ -0,0 +1,305 @@
+//! A load generator: hammer one URL from a number of concurrent connections,
+//! then report throughput and latency. Start the server first, in another
+//! terminal, with `cargo run --release --bin async-http-server`.
+//!
+//! ```text
+//! cargo run --release --bin bench -- -c 16 -d 10 http://127.0.0.1:7878/
+//! cargo run --release --bin bench -- -c 4 -n 20 http://127.0.0.1:7878/sleep
+//! ```
+//!
+//! The server gives each open connection a worker of its own, up to
+//! `workers.max` (16 by default). Past that, connections queue for a worker,
+//! and kept-alive ones are only closed to make room between requests, so
+//! with more connections than workers some of them spend their time waiting
+//! and reconnecting. Expect the latency tail, and the spread of requests per
+//! connection in the report, to show it.
+
+use std::{
+    collections::BTreeMap,
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use async_http_server::client::{Client, ClientRequest};
+use futures::future;
+
+const USAGE: &str = "\
+usage: bench [options] <url>
+
+options:
+    -c, --connections <n>   concurrent connections (default 8)
+    -d, --duration <secs>   run for this long (default 10)
+    -n, --requests <n>      send this many requests in total, instead
+    -t, --timeout <secs>    per-read timeout (default 30)
+        --no-keep-alive     open a new connection for every request
+";
+
+/// Stop after a fixed number of requests, or once time is up.
+enum Limit {
+    Requests(usize),
+    Duration(Duration),
+}
+
+struct Options {
+    url: String,
+    connections: usize,
+    limit: Limit,
+    timeout: Duration,
+    keep_alive: bool,
+}
+
+/// What one connection saw.
+#[derive(Default)]
+struct Results {
+    latencies: Vec<Duration>,
+    statuses: BTreeMap<u16, usize>,
+    errors: BTreeMap<String, usize>,
+    bytes: usize,
+}
+
+#[tokio::main]
+async fn main() {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{message}\n\n{USAGE}");
+            process::exit(2);
+        }
+    };
+
+    // Check the server is there at all before hammering it, rather than
+    // reporting a few hundred thousand refused connections.
+    let probe = Client::new()
+        .timeout(options.timeout)
+        .connect_timeout(options.timeout)
+        .get(&options.url)
+        .await;
+    if let Err(error) = probe {
+        eprintln!("Cannot reach {}: {error}", options.url);
+        process::exit(1);
+    }
+
+    match options.limit {
+        Limit::Requests(count) => println!(
+            "Sending {count} requests to {} over {} connections",
+            options.url, options.connections
+        ),
+        Limit::Duration(duration) => println!(
+            "Running for {duration:?} against {} over {} connections",
+            options.url, options.connections
+        ),
+    }
+
+    let options = Arc::new(options);
+    let remaining = Arc::new(AtomicUsize::new(match options.limit {
+        Limit::Requests(count) => count,
+        Limit::Duration(_) => usize::MAX,
+    }));
+
+    let start = Instant::now();
+    let deadline = match options.limit {
+        Limit::Duration(duration) => Some(start + duration),
+        Limit::Requests(_) => None,
+    };
+
+    let tasks = (0..options.connections).map(|_| {
+        let options = Arc::clone(&options);
+        let remaining = Arc::clone(&remaining);
+        tokio::spawn(run(options, remaining, deadline))
+    });
+
+    let mut results = Results::default();
+    let mut per_connection = Vec::new();
+    for task in future::join_all(tasks).await {
+        let task = task.expect("a connection task panicked");
+        per_connection.push(task.latencies.len());
+        results.latencies.extend(task.latencies);
+        results.bytes += task.bytes;
+        for (status, count) in task.statuses {
+            *results.statuses.entry(status).or_default() += count;
+        }
+        for (error, count) in task.errors {
+            *results.errors.entry(error).or_default() += count;
+        }
+    }
+
+    report(&mut results, &mut per_connection, start.elapsed());
+}
+
+/// Send requests one after another until there are none left to send or the
+/// deadline passes. Each call is one connection's worth of load.
+async fn run(
+    options: Arc<Options>,
+    remaining: Arc<AtomicUsize>,
+    deadline: Option<Instant>,
+) -> Results {
+    let idle = if options.keep_alive { 1 } else { 0 };
+    let client = Client::new()
+        .timeout(options.timeout)
+        .connect_timeout(options.timeout)
+        .max_idle_per_host(idle);
+
+    let mut results = Results::default();
+    loop {
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            break;
+        }
+
+        let claimed =
+            remaining.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                n.checked_sub(1)
+            });
+        if claimed.is_err() {
+            break;
+        }
+
+        let mut request = ClientRequest::new("GET", &options.url);
+        if !options.keep_alive {
+            request = request.with_header("Connection", "close");
+        }
+
+        let sent = Instant::now();
+        let outcome = match client.send(request).await {
+            Ok(mut response) => match response.to_vec(usize::MAX).await {
+                Ok(body) => Ok((response.status, body.len())),
+                Err(error) => Err(error),
+            },
+            Err(error) => Err(error),
+        };
+
+        match outcome {
+            Ok((status, bytes)) => {
+                results.latencies.push(sent.elapsed());
+                results.bytes += bytes;
+                *results.statuses.entry(status).or_default() += 1;
+            }
+            Err(error) => {
+                *results.errors.entry(error.to_string()).or_default() += 1;
+            }
+        }
+    }
+
+    results
+}
+
+fn report(
+    results: &mut Results,
+    per_connection: &mut [usize],
+    elapsed: Duration,
+) {
+    let completed = results.latencies.len();
+    let errors: usize = results.errors.values().sum();
+    let seconds = elapsed.as_secs_f64();
+
+    println!();
+    println!("Finished in {seconds:.2}s");
+    println!("  requests:   {completed} completed, {errors} failed");
+    println!("  throughput: {:.1} requests/s", completed as f64 / seconds);
+    println!(
+        "  transfer:   {:.1} KiB/s (bodies only)",
+        results.bytes as f64 / 1024.0 / seconds
+    );
+
+    if !results.statuses.is_empty() {
+        println!("  statuses:");
+        for (status, count) in &results.statuses {
+            println!("    {status}: {count}");
+        }
+    }
+
+    if completed > 0 {
+        results.latencies.sort_unstable();
+        let latencies = &results.latencies;
+        println!("  latency:");
+        for (label, p) in [("p50", 0.50), ("p90", 0.90), ("p99", 0.99)] {
+            println!("    {label}: {:?}", percentile(latencies, p));
+        }
+        println!("    max: {:?}", latencies[completed - 1]);
+
+        // A few requests stuck behind a busy server can hide under a good p99,
+        // so say how many there were.
+        let p99 = percentile(latencies, 0.99);
+        let slow = latencies.iter().filter(|&&latency| latency > p99).count();
+        println!("    over p99: {slow}");
+
+        // Uneven counts mean some connections got far less of the server's
+        // time than others.
+        per_connection.sort_unstable();
+        println!(
+            "  requests per connection: min {}, median {}, max {}",
+            per_connection[0],
+            per_connection[per_connection.len() / 2],
+            per_connection[per_connection.len() - 1],
+        );
+    }
+
+    if !results.errors.is_empty() {
+        println!("  errors:");
+        for (error, count) in &results.errors {
+            println!("    {count} × {error}");
+        }
+    }
+}
+
+/// The nearest-rank percentile of an already sorted, non-empty slice.
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let rank = (p * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn parse_args(
+    mut args: impl Iterator<Item = String>,
+) -> Result<Options, String> {
+    let mut url = None;
+    let mut connections = 8;
+    let mut limit = Limit::Duration(Duration::from_secs(10));
+    let mut timeout = Duration::from_secs(30);
+    let mut keep_alive = true;
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("{arg} needs a value"))
+                .and_then(|value| {
+                    value
+                        .parse::<u64>()
+                        .map_err(|_| format!("bad value for {arg}: {value}"))
+                })
+        };
+
+        match arg.as_str() {
+            "-c" | "--connections" => connections = value()? as usize,
+            "-d" | "--duration" => {
+                limit = Limit::Duration(Duration::from_secs(value()?))
+            }
+            "-n" | "--requests" => limit = Limit::Requests(value()? as usize),
+            "-t" | "--timeout" => timeout = Duration::from_secs(value()?),
+            "--no-keep-alive" => keep_alive = false,
+            "-h" | "--help" => {
+                print!("{USAGE}");
+                process::exit(0);
+            }
+            _ if arg.starts_with('-') => {
+                return Err(format!("unknown option: {arg}"))
+            }
+            _ if url.is_none() => url = Some(arg),
+            _ => return Err(format!("unexpected argument: {arg}")),
+        }
+    }
+
+    if connections == 0 {
+        return Err(String::from("need at least one connection"));
+    }
+
+    Ok(Options {
+        url: url.ok_or("no URL given")?,
+        connections,
+        limit,
+        timeout,
+        keep_alive,
+    })
+}
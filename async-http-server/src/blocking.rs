@@ -0,0 +1,180 @@
+use std::{
+    collections::VecDeque,
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    sync::{Arc, Condvar, Mutex},
+    task::{Context, Poll},
+    thread,
+    time::Duration,
+};
+
+use futures::{channel::oneshot, FutureExt};
+
+type BlockingJob = Box<dyn FnOnce() + Send + 'static>;
+
+/// A pool of threads for work which blocks: hashing, compression, synchronous
+/// file APIs, and the like. Running that on one of the `ThreadPool` workers
+/// would stall every other task on that worker until it finished.
+///
+/// There are no threads at all until there is work to do. A new thread starts
+/// whenever a job arrives and every existing thread is busy, up to the limit,
+/// and a thread which sits idle for the keep-alive period goes away again.
+///
+/// Cloning a `BlockingPool` gives another handle to the same threads, so
+/// handlers can hold onto one.
+#[derive(Clone)]
+pub struct BlockingPool {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    available: Condvar,
+    max_threads: usize,
+    keep_alive: Duration,
+}
+
+struct State {
+    queue: VecDeque<BlockingJob>,
+    threads: usize,
+    idle: usize,
+    next_id: usize,
+    shutdown: bool,
+}
+
+impl BlockingPool {
+    /// # Panics
+    ///
+    /// Panics if `max_threads` is zero.
+    pub fn new(max_threads: usize, keep_alive: Duration) -> BlockingPool {
+        assert!(max_threads > 0);
+
+        BlockingPool {
+            shared: Arc::new(Shared {
+                state: Mutex::new(State {
+                    queue: VecDeque::new(),
+                    threads: 0,
+                    idle: 0,
+                    next_id: 0,
+                    shutdown: false,
+                }),
+                available: Condvar::new(),
+                max_threads,
+                keep_alive,
+            }),
+        }
+    }
+
+    /// Run `f` on a blocking thread. The returned handle resolves to whatever
+    /// `f` returns, or to the panic payload if `f` panics, just like
+    /// `JoinHandle::join`. After `shutdown`, `f` never runs and the handle
+    /// resolves to an error.
+    pub fn spawn<F, T>(&self, f: F) -> BlockingHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+        let job = Box::new(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(f));
+
+            // The caller may have stopped waiting; that is fine.
+            let _ = sender.send(result);
+        });
+
+        let mut state = self.shared.state.lock().unwrap();
+
+        // There may be no threads left to run it. Dropping the job drops its
+        // sender, so the handle resolves to an error instead of hanging.
+        if state.shutdown {
+            drop(state);
+            drop(job);
+            return BlockingHandle { receiver };
+        }
+
+        state.queue.push_back(job);
+
+        // More queued jobs than idle threads to take them means someone will
+        // have to wait, so start another thread if we are allowed to.
+        if state.queue.len() > state.idle
+            && state.threads < self.shared.max_threads
+        {
+            let id = state.next_id;
+            state.next_id += 1;
+            state.threads += 1;
+            println!("Starting blocking thread {id}");
+
+            let shared = Arc::clone(&self.shared);
+            thread::spawn(move || run(id, shared));
+        }
+
+        self.shared.available.notify_one();
+        BlockingHandle { receiver }
+    }
+
+    /// Stop taking new jobs. The threads already running finish whatever is
+    /// queued and then exit. They are not joined: a blocking job may never
+    /// finish, and it should not hold up shutting down everything else.
+    pub(crate) fn shutdown(&self) {
+        self.shared.state.lock().unwrap().shutdown = true;
+        self.shared.available.notify_all();
+    }
+}
+
+fn run(id: usize, shared: Arc<Shared>) {
+    let mut state = shared.state.lock().unwrap();
+    loop {
+        if let Some(job) = state.queue.pop_front() {
+            drop(state);
+            job();
+            state = shared.state.lock().unwrap();
+            continue;
+        }
+
+        if state.shutdown {
+            println!("Blocking thread {id} shutting down.");
+            break;
+        }
+
+        state.idle += 1;
+        let (next, wait) = shared
+            .available
+            .wait_timeout(state, shared.keep_alive)
+            .unwrap();
+        state = next;
+        state.idle -= 1;
+
+        if wait.timed_out() && state.queue.is_empty() {
+            println!("Blocking thread {id} idle; retiring.");
+            break;
+        }
+    }
+
+    state.threads -= 1;
+}
+
+/// The eventual result of a job passed to `BlockingPool::spawn`.
+pub struct BlockingHandle<T> {
+    receiver: oneshot::Receiver<thread::Result<T>>,
+}
+
+impl<T> Future for BlockingHandle<T> {
+    type Output = thread::Result<T>;
+
+    fn poll(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Self::Output> {
+        match self.receiver.poll_unpin(cx) {
+            Poll::Ready(Ok(result)) => Poll::Ready(result),
+
+            // The job was dropped without running: it was spawned after
+            // `shutdown`, or its thread went away underneath it.
+            Poll::Ready(Err(oneshot::Canceled)) => {
+                Poll::Ready(Err(Box::new("blocking job was dropped unrun")))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
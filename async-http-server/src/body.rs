@@ -0,0 +1,323 @@
+use std::{
+    future::Future,
+    io, mem,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures::{
+    stream::{self, BoxStream},
+    Stream, StreamExt, TryStreamExt,
+};
+use tokio::io::AsyncBufReadExt;
+
+use crate::http::{self, invalid, Headers, Reader};
+
+/// A request body, as a `Stream` of byte chunks read straight off the
+/// connection. Nothing is buffered beyond what the connection's own
+/// `BufReader` holds, so a handler can process an arbitrarily large upload a
+/// chunk at a time, or ignore it entirely.
+pub struct Body<'a> {
+    chunks: BoxStream<'a, io::Result<Vec<u8>>>,
+}
+
+impl<'a> Body<'a> {
+    /// A body with nothing in it.
+    pub fn empty() -> Body<'a> {
+        Body {
+            chunks: stream::empty().boxed(),
+        }
+    }
+
+    /// Work out from the headers how the body is framed, and get ready to
+    /// read it that way: chunked, a fixed `Content-Length`, or no body at all.
+    pub(crate) fn new(
+        reader: &'a mut Reader<'a>,
+        headers: &Headers,
+    ) -> io::Result<Body<'a>> {
+        let framing = Framing::for_request(headers)?;
+        let chunks = stream::try_unfold(
+            (reader, framing),
+            |(reader, mut framing)| async move {
+                let chunk = framing.next_chunk(reader).await?;
+                Ok(chunk.map(|chunk| (chunk, (reader, framing))))
+            },
+        );
+
+        Ok(Body {
+            chunks: chunks.boxed(),
+        })
+    }
+
+    /// Run `first` when the body is first polled, before any of it is read: to
+    /// tell a client waiting on `Expect: 100-continue` to go ahead, say. If it
+    /// fails, the body ends with its error.
+    pub fn before_reading<F>(&mut self, first: F)
+    where
+        F: Future<Output = io::Result<()>> + Send + 'a,
+    {
+        let chunks = mem::replace(&mut self.chunks, stream::empty().boxed());
+        self.chunks = stream::once(async move { first.await.map(|()| chunks) })
+            .try_flatten()
+            .boxed();
+    }
+
+    /// Read the whole body into memory, failing if it turns out to be longer
+    /// than `limit` bytes. Only for bodies you know to be small!
+    pub async fn to_vec(&mut self, limit: usize) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        while let Some(chunk) = self.next().await {
+            let chunk = chunk?;
+            if bytes.len() + chunk.len() > limit {
+                return Err(invalid(format!("body longer than {limit} bytes")));
+            }
+
+            bytes.extend_from_slice(&chunk);
+        }
+
+        Ok(bytes)
+    }
+}
+
+impl Stream for Body<'_> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        self.chunks.poll_next_unpin(cx)
+    }
+}
+
+/// How a message body is delimited on the wire, and how much of it is left.
+/// Requests and responses share this; only the rules for picking one differ.
+#[derive(Debug)]
+pub(crate) enum Framing {
+    /// A fixed `Content-Length`, with this many bytes still to come.
+    Length(u64),
+
+    /// `Transfer-Encoding: chunked`.
+    Chunked(Chunked),
+
+    /// No length at all: the body is everything until the connection closes.
+    /// Only responses are allowed to do this.
+    UntilClose,
+
+    /// Nothing (more) to read.
+    Done,
+}
+
+#[derive(Debug)]
+pub(crate) enum Chunked {
+    /// Expecting a chunk-size line next.
+    Size,
+
+    /// Partway through a chunk, with this many bytes of it left.
+    Data(u64),
+}
+
+impl Framing {
+    /// A request body is chunked, a fixed `Content-Length`, or not there.
+    pub(crate) fn for_request(headers: &Headers) -> io::Result<Framing> {
+        Ok(Framing::from_headers(headers)?.unwrap_or(Framing::Done))
+    }
+
+    /// A response body follows the same rules as a request body, except that
+    /// some responses never have one, and one with no length runs until the
+    /// connection closes.
+    pub(crate) fn for_response(
+        status: u16,
+        request_method: &str,
+        headers: &Headers,
+    ) -> io::Result<Framing> {
+        if request_method == "HEAD" || http::is_bodiless(status) {
+            return Ok(Framing::Done);
+        }
+
+        Ok(Framing::from_headers(headers)?.unwrap_or(Framing::UntilClose))
+    }
+
+    fn from_headers(headers: &Headers) -> io::Result<Option<Framing>> {
+        let chunked = headers.get_all("Transfer-Encoding").any(|value| {
+            value
+                .split(',')
+                .any(|coding| coding.trim().eq_ignore_ascii_case("chunked"))
+        });
+
+        if chunked {
+            return Ok(Some(Framing::Chunked(Chunked::Size)));
+        }
+
+        let Some(length) = headers.get("Content-Length") else {
+            return Ok(None);
+        };
+
+        let length = length
+            .parse::<u64>()
+            .map_err(|_| invalid(format!("bad Content-Length: {length}")))?;
+
+        Ok(Some(Framing::Length(length)))
+    }
+
+    pub(crate) fn is_done(&self) -> bool {
+        matches!(self, Framing::Done)
+    }
+
+    /// The next chunk of the body, or `None` once all of it has been read.
+    pub(crate) async fn next_chunk(
+        &mut self,
+        reader: &mut Reader<'_>,
+    ) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            match self {
+                Framing::Done => return Ok(None),
+
+                Framing::Length(0) => *self = Framing::Done,
+
+                Framing::Length(remaining) => {
+                    let chunk = next_chunk(reader, *remaining).await?;
+                    *remaining -= chunk.len() as u64;
+                    return Ok(Some(chunk));
+                }
+
+                Framing::UntilClose => {
+                    let available = reader.fill_buf().await?;
+                    if available.is_empty() {
+                        *self = Framing::Done;
+                        continue;
+                    }
+
+                    let chunk = available.to_vec();
+                    reader.consume(chunk.len());
+                    return Ok(Some(chunk));
+                }
+
+                Framing::Chunked(Chunked::Size) => {
+                    let line =
+                        http::read_line(reader).await?.ok_or_else(|| {
+                            invalid("connection closed before the last chunk")
+                        })?;
+
+                    // Chunk extensions are allowed after a `;`, and we are
+                    // allowed to ignore them, so we do.
+                    let size =
+                        line.split(';').next().unwrap_or_default().trim();
+                    let size = u64::from_str_radix(size, 16).map_err(|_| {
+                        invalid(format!("bad chunk size: {line}"))
+                    })?;
+
+                    if size == 0 {
+                        // Trailers, which we also ignore.
+                        Headers::read(reader).await?;
+                        *self = Framing::Done;
+                    } else {
+                        *self = Framing::Chunked(Chunked::Data(size));
+                    }
+                }
+
+                Framing::Chunked(Chunked::Data(0)) => {
+                    match http::read_line(reader).await? {
+                        Some(line) if line.is_empty() => {}
+                        _ => return Err(invalid("chunk not followed by CRLF")),
+                    }
+
+                    *self = Framing::Chunked(Chunked::Size);
+                }
+
+                Framing::Chunked(Chunked::Data(remaining)) => {
+                    let chunk = next_chunk(reader, *remaining).await?;
+                    *remaining -= chunk.len() as u64;
+                    return Ok(Some(chunk));
+                }
+            }
+        }
+    }
+}
+
+/// Hand out whatever the reader already has buffered, up to `max` bytes.
+async fn next_chunk(reader: &mut Reader<'_>, max: u64) -> io::Result<Vec<u8>> {
+    let available = reader.fill_buf().await?;
+    if available.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "connection closed in the middle of the body",
+        ));
+    }
+
+    let length = available
+        .len()
+        .min(usize::try_from(max).unwrap_or(usize::MAX));
+    let chunk = available[..length].to_vec();
+    reader.consume(length);
+    Ok(chunk)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn read_all(
+        wire: &mut &[u8],
+        header: (&str, &str),
+    ) -> io::Result<Vec<Vec<u8>>> {
+        let mut headers = Headers::new();
+        headers.append(header.0, header.1);
+        Body::new(wire, &headers)?.try_collect().await
+    }
+
+    #[tokio::test]
+    async fn chunk_extensions_and_trailers_are_skipped() {
+        let mut wire: &[u8] = b"5;name=value\r\nhello\r\n\
+            6 ; quoted=\"a;b\"\r\n world\r\n\
+            0;last\r\nExpires: never\r\nX-Check: 1\r\n\r\n\
+            GET / HTTP/1.1\r\n";
+
+        let chunks = read_all(&mut wire, ("Transfer-Encoding", "chunked"))
+            .await
+            .unwrap();
+
+        assert_eq!(chunks, [b"hello".to_vec(), b" world".to_vec()]);
+        // Nothing past the trailers was touched: it is the next request.
+        assert_eq!(wire, b"GET / HTTP/1.1\r\n");
+    }
+
+    #[tokio::test]
+    async fn content_length_stops_at_the_length() {
+        let mut wire: &[u8] = b"hello world";
+        let chunks =
+            read_all(&mut wire, ("Content-Length", "5")).await.unwrap();
+
+        assert_eq!(chunks, [b"hello".to_vec()]);
+        assert_eq!(wire, b" world");
+    }
+
+    #[tokio::test]
+    async fn bad_chunk_size_is_an_error() {
+        let mut wire: &[u8] = b"zz\r\nhello\r\n0\r\n\r\n";
+        let error = read_all(&mut wire, ("Transfer-Encoding", "chunked"))
+            .await
+            .unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn before_reading_runs_once_and_first() {
+        let mut wire: &[u8] = b"hello";
+        let mut headers = Headers::new();
+        headers.append("Content-Length", "5");
+
+        let (sender, mut receiver) = futures::channel::mpsc::unbounded();
+        let mut body = Body::new(&mut wire, &headers).unwrap();
+        body.before_reading(async move {
+            sender.unbounded_send(()).unwrap();
+            Ok(())
+        });
+
+        assert!(receiver.try_next().is_err(), "ran before being polled");
+        assert_eq!(body.to_vec(64).await.unwrap(), b"hello");
+        assert_eq!(receiver.try_next().unwrap(), Some(()));
+        assert_eq!(receiver.try_next().unwrap(), None);
+    }
+}
@@ -0,0 +1,638 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    io,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures::{
+    stream::{self, BoxStream},
+    StreamExt,
+};
+use tokio::{
+    io::{AsyncWriteExt, BufReader},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+    time,
+};
+
+use crate::{
+    body::Framing,
+    http::{self, invalid, Headers},
+};
+
+/// How long to wait for a TCP connection to be set up.
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait on any single read or write once connected.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How many idle connections to keep around for each host.
+const DEFAULT_MAX_IDLE_PER_HOST: usize = 8;
+
+/// A small HTTP/1.1 client. It speaks plain `http://` over TCP, keeps
+/// connections alive between requests, and reads response bodies a chunk at
+/// a time, just like the server reads request bodies.
+///
+/// Cloning a `Client` gives another handle to the same connection pool.
+#[derive(Clone)]
+pub struct Client {
+    idle: Arc<Mutex<HashMap<String, Vec<Connection>>>>,
+    connect_timeout: Duration,
+    timeout: Duration,
+    max_idle_per_host: usize,
+}
+
+struct Connection {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl Client {
+    pub fn new() -> Client {
+        Client {
+            idle: Arc::new(Mutex::new(HashMap::new())),
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            timeout: DEFAULT_TIMEOUT,
+            max_idle_per_host: DEFAULT_MAX_IDLE_PER_HOST,
+        }
+    }
+
+    /// Give up on connecting after `timeout`.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Client {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Give up on any single read or write which takes longer than `timeout`.
+    /// This bounds how long a server can go quiet, not how long a whole
+    /// response can take: a slow but steady body never times out.
+    pub fn timeout(mut self, timeout: Duration) -> Client {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Keep at most `max` idle connections to each host. Zero turns off
+    /// keep-alive entirely.
+    pub fn max_idle_per_host(mut self, max: usize) -> Client {
+        self.max_idle_per_host = max;
+        self
+    }
+
+    pub async fn get(&self, url: &str) -> io::Result<ClientResponse> {
+        self.send(ClientRequest::new("GET", url)).await
+    }
+
+    /// Send the request and read the response head. The body is left on the
+    /// connection for the caller to read, and the connection goes back into
+    /// the pool once all of it has been.
+    pub async fn send(
+        &self,
+        mut request: ClientRequest,
+    ) -> io::Result<ClientResponse> {
+        let url = Url::parse(&request.url)?;
+        request.check()?;
+
+        // A pooled connection may have been closed by the server while it sat
+        // idle, which we only find out by trying it. If that happens, try once
+        // more on a fresh connection, but only if the body can be sent again
+        // and sending the request twice is harmless: the server may have
+        // acted on it before the connection went.
+        if let Some(connection) = self.take_idle(&url.address) {
+            match self.exchange(connection, &url, &mut request).await {
+                Err(error)
+                    if is_stale(&error)
+                        && request.idempotent()
+                        && request.body.replayable() =>
+                {
+                    // Fall through to a fresh connection.
+                }
+                result => return result,
+            }
+        }
+
+        let connection = self.connect(&url.address).await?;
+        self.exchange(connection, &url, &mut request).await
+    }
+
+    async fn connect(&self, address: &str) -> io::Result<Connection> {
+        let stream =
+            within(self.connect_timeout, TcpStream::connect(address)).await?;
+        stream.set_nodelay(true)?;
+
+        let (reader, writer) = stream.into_split();
+        Ok(Connection {
+            reader: BufReader::new(reader),
+            writer,
+        })
+    }
+
+    async fn exchange(
+        &self,
+        mut connection: Connection,
+        url: &Url,
+        request: &mut ClientRequest,
+    ) -> io::Result<ClientResponse> {
+        let mut head = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\n",
+            request.method, url.target, url.host
+        );
+
+        for (name, value) in request.headers.iter() {
+            head += &format!("{name}: {value}\r\n");
+        }
+
+        match &request.body {
+            RequestBody::Empty => {}
+            RequestBody::Bytes(bytes) => {
+                head += &format!("Content-Length: {}\r\n", bytes.len());
+            }
+            RequestBody::Stream(_) => head += "Transfer-Encoding: chunked\r\n",
+        }
+
+        head += "\r\n";
+
+        let writer = &mut connection.writer;
+        within(self.timeout, writer.write_all(head.as_bytes())).await?;
+        match &mut request.body {
+            RequestBody::Empty => {}
+            RequestBody::Bytes(bytes) => {
+                within(self.timeout, writer.write_all(bytes)).await?;
+            }
+            RequestBody::Stream(chunks) => {
+                while let Some(chunk) = chunks.next().await {
+                    let chunk = chunk?;
+                    if chunk.is_empty() {
+                        continue;
+                    }
+
+                    let framed = [
+                        format!("{:x}\r\n", chunk.len()).as_bytes(),
+                        &chunk,
+                        b"\r\n",
+                    ]
+                    .concat();
+                    within(self.timeout, writer.write_all(&framed)).await?;
+                }
+
+                within(self.timeout, writer.write_all(b"0\r\n\r\n")).await?;
+            }
+        }
+
+        within(self.timeout, writer.flush()).await?;
+
+        // Skip any `100 Continue`s and the like: the real response follows.
+        let (version, status, reason, headers) = loop {
+            let head =
+                within(self.timeout, read_head(&mut connection.reader)).await?;
+            if !(100..200).contains(&head.1) || head.1 == 101 {
+                break head;
+            }
+        };
+
+        let framing = Framing::for_response(status, &request.method, &headers)?;
+
+        // HTTP/1.0 closes by default, and so does anything whose body runs
+        // until the connection does.
+        let closes = headers
+            .get_all("Connection")
+            .any(|value| value.eq_ignore_ascii_case("close"));
+        let reusable = version == "HTTP/1.1"
+            && !closes
+            && !matches!(framing, Framing::UntilClose);
+
+        let mut response = ClientResponse {
+            version,
+            status,
+            reason,
+            headers,
+            connection: Some(connection),
+            framing,
+            reusable,
+            address: url.address.clone(),
+            client: self.clone(),
+        };
+
+        // Bodiless responses are finished already.
+        response.release_if_done();
+        Ok(response)
+    }
+
+    fn take_idle(&self, address: &str) -> Option<Connection> {
+        self.idle.lock().unwrap().get_mut(address)?.pop()
+    }
+
+    fn put_idle(&self, address: &str, connection: Connection) {
+        let mut idle = self.idle.lock().unwrap();
+        let connections = idle.entry(address.to_string()).or_default();
+        if connections.len() < self.max_idle_per_host {
+            connections.push(connection);
+        }
+    }
+}
+
+impl Default for Client {
+    fn default() -> Client {
+        Client::new()
+    }
+}
+
+/// A request to send with a `Client`. `Host`, `Content-Length`, and
+/// `Transfer-Encoding` are filled in for you, so do not set them by hand.
+pub struct ClientRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Headers,
+    body: RequestBody,
+}
+
+enum RequestBody {
+    Empty,
+    Bytes(Vec<u8>),
+
+    /// Sent with chunked encoding as the stream produces it.
+    Stream(BoxStream<'static, io::Result<Vec<u8>>>),
+}
+
+impl RequestBody {
+    /// Whether the body can be sent a second time. A stream is used up by
+    /// sending it once.
+    fn replayable(&self) -> bool {
+        !matches!(self, RequestBody::Stream(_))
+    }
+}
+
+impl ClientRequest {
+    pub fn new(method: &str, url: &str) -> ClientRequest {
+        ClientRequest {
+            method: method.to_string(),
+            url: url.to_string(),
+            headers: Headers::new(),
+            body: RequestBody::Empty,
+        }
+    }
+
+    pub fn with_header(
+        mut self,
+        name: impl Into<String>,
+        value: impl Into<String>,
+    ) -> ClientRequest {
+        self.headers.append(name, value);
+        self
+    }
+
+    pub fn with_body(mut self, body: impl Into<Vec<u8>>) -> ClientRequest {
+        self.body = RequestBody::Bytes(body.into());
+        self
+    }
+
+    /// Send the body a chunk at a time as `chunks` produces it, rather than
+    /// all at once.
+    pub fn with_stream(
+        mut self,
+        chunks: BoxStream<'static, io::Result<Vec<u8>>>,
+    ) -> ClientRequest {
+        self.body = RequestBody::Stream(chunks);
+        self
+    }
+
+    /// Refuse anything which would let the method or a header break out of
+    /// its line and smuggle in headers, or a whole request, of its own.
+    fn check(&self) -> io::Result<()> {
+        let line_break = |text: &str| text.contains(['\r', '\n']);
+
+        if self.method.is_empty()
+            || self.method.contains(|c: char| c.is_ascii_whitespace())
+        {
+            return Err(invalid(format!("bad method: {:?}", self.method)));
+        }
+
+        for (name, value) in self.headers.iter() {
+            if name.is_empty()
+                || name.contains(|c: char| c.is_ascii_whitespace() || c == ':')
+                || line_break(value)
+            {
+                return Err(invalid(format!(
+                    "bad header: {name:?}: {value:?}"
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether sending the request more than once has the same effect as
+    /// sending it once, per RFC 9110 section 9.2.2.
+    fn idempotent(&self) -> bool {
+        matches!(
+            self.method.as_str(),
+            "GET" | "HEAD" | "OPTIONS" | "TRACE" | "PUT" | "DELETE"
+        )
+    }
+}
+
+/// A response head, plus its body, which has *not* been read yet. Read all of
+/// it to let the connection be used again; dropping the response partway
+/// through closes the connection instead.
+pub struct ClientResponse {
+    pub version: String,
+    pub status: u16,
+    pub reason: String,
+    pub headers: Headers,
+    connection: Option<Connection>,
+    framing: Framing,
+    reusable: bool,
+    address: String,
+    client: Client,
+}
+
+impl ClientResponse {
+    /// The next chunk of the body, or `None` at its end.
+    pub async fn chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let Some(connection) = &mut self.connection else {
+            return Ok(None);
+        };
+
+        let next = self.framing.next_chunk(&mut connection.reader);
+        let chunk = within(self.client.timeout, next).await?;
+        self.release_if_done();
+        Ok(chunk)
+    }
+
+    /// Read the whole body into memory, failing if it turns out to be longer
+    /// than `limit` bytes.
+    pub async fn to_vec(&mut self, limit: usize) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        while let Some(chunk) = self.chunk().await? {
+            if bytes.len() + chunk.len() > limit {
+                return Err(invalid(format!("body longer than {limit} bytes")));
+            }
+
+            bytes.extend_from_slice(&chunk);
+        }
+
+        Ok(bytes)
+    }
+
+    /// Read the whole body as text; see `to_vec`.
+    pub async fn text(&mut self, limit: usize) -> io::Result<String> {
+        let bytes = self.to_vec(limit).await?;
+        String::from_utf8(bytes).map_err(|error| invalid(error.to_string()))
+    }
+
+    /// The body as a `Stream` of chunks, for handing off to something which
+    /// wants one.
+    pub fn into_stream(self) -> BoxStream<'static, io::Result<Vec<u8>>> {
+        stream::try_unfold(self, |mut response| async move {
+            let chunk = response.chunk().await?;
+            Ok(chunk.map(|chunk| (chunk, response)))
+        })
+        .boxed()
+    }
+
+    fn release_if_done(&mut self) {
+        if !self.framing.is_done() {
+            return;
+        }
+
+        if let Some(connection) = self.connection.take() {
+            if self.reusable {
+                self.client.put_idle(&self.address, connection);
+            }
+        }
+    }
+}
+
+/// The parts of an `http://` URL the client needs.
+struct Url {
+    /// For the `Host` header: the host, plus the port if one was given.
+    host: String,
+
+    /// For connecting: always includes a port.
+    address: String,
+
+    /// For the request line: the path and query.
+    target: String,
+}
+
+impl Url {
+    fn parse(url: &str) -> io::Result<Url> {
+        let rest = url.strip_prefix("http://").ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("only http:// URLs are supported: {url}"),
+            )
+        })?;
+
+        // Whitespace has to be percent-encoded, and a line break would end
+        // the request line early.
+        if url.contains(|c: char| c.is_ascii_whitespace() || c.is_control()) {
+            return Err(invalid(format!("bad character in URL: {url:?}")));
+        }
+
+        let (host, target) = match rest.find(['/', '?']) {
+            Some(at) => (&rest[..at], &rest[at..]),
+            None => (rest, "/"),
+        };
+
+        if host.is_empty() {
+            return Err(invalid(format!("no host in URL: {url}")));
+        }
+
+        let target = if target.starts_with('?') {
+            format!("/{target}")
+        } else {
+            target.to_string()
+        };
+
+        // A colon after the closing bracket of an IPv6 literal, or anywhere
+        // in anything else, means there is already a port.
+        let has_port = match host.rsplit_once(']') {
+            Some((_, after)) => after.starts_with(':'),
+            None => host.contains(':'),
+        };
+        let address = if has_port {
+            host.to_string()
+        } else {
+            format!("{host}:80")
+        };
+
+        Ok(Url {
+            host: host.to_string(),
+            address,
+            target,
+        })
+    }
+}
+
+/// Read a status line and headers.
+async fn read_head(
+    reader: &mut http::Reader<'_>,
+) -> io::Result<(String, u16, String, Headers)> {
+    let status_line = http::read_line(reader).await?.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "connection closed before the response",
+        )
+    })?;
+
+    let mut parts = status_line.splitn(3, ' ');
+    let (Some(version), Some(status)) = (parts.next(), parts.next()) else {
+        return Err(invalid(format!("bad status line: {status_line}")));
+    };
+
+    let status = status
+        .parse()
+        .map_err(|_| invalid(format!("bad status line: {status_line}")))?;
+    let reason = parts.next().unwrap_or("").to_string();
+    let headers = Headers::read(reader).await?;
+
+    Ok((version.to_string(), status, reason, headers))
+}
+
+/// Whether an error looks like the server having closed a kept-alive
+/// connection, rather than anything wrong with the request.
+fn is_stale(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+    )
+}
+
+/// Run `future`, turning it taking longer than `limit` into a `TimedOut`
+/// error.
+async fn within<T>(
+    limit: Duration,
+    future: impl Future<Output = io::Result<T>>,
+) -> io::Result<T> {
+    match time::timeout(limit, future).await {
+        Ok(result) => result,
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("no progress for {limit:?}"),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt},
+        net::TcpListener,
+        sync::oneshot,
+    };
+
+    use super::*;
+
+    #[test]
+    fn url_parts() {
+        let url = Url::parse("http://example.com").unwrap();
+        assert_eq!(url.host, "example.com");
+        assert_eq!(url.address, "example.com:80");
+        assert_eq!(url.target, "/");
+
+        let url = Url::parse("http://[::1]:8080/a/b?c=d").unwrap();
+        assert_eq!(url.host, "[::1]:8080");
+        assert_eq!(url.address, "[::1]:8080");
+        assert_eq!(url.target, "/a/b?c=d");
+
+        let url = Url::parse("http://[::1]?q").unwrap();
+        assert_eq!(url.address, "[::1]:80");
+        assert_eq!(url.target, "/?q");
+
+        assert!(Url::parse("https://example.com/").is_err());
+        assert!(Url::parse("http:///path").is_err());
+    }
+
+    #[tokio::test]
+    async fn line_breaks_cannot_be_smuggled_in() {
+        let client = Client::new();
+        let bad = [
+            ClientRequest::new("GET", "http://127.0.0.1:9/a\r\nX-Evil: 1"),
+            ClientRequest::new("GET /x HTTP/1.1\r\n", "http://127.0.0.1:9/"),
+            ClientRequest::new("GET", "http://127.0.0.1:9/")
+                .with_header("X-Fine", "a\r\nX-Evil: 1"),
+            ClientRequest::new("GET", "http://127.0.0.1:9/")
+                .with_header("X-Evil: 1\r\nX-Fine", "a"),
+        ];
+
+        for request in bad {
+            let error = client.send(request).await.err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    /// Answer every request with which connection and which request on it
+    /// this is, e.g. `2.1`. Closes the first connection after two requests,
+    /// and says so on `closed`.
+    async fn serve(listener: TcpListener, closed: oneshot::Sender<()>) {
+        let mut closed = Some(closed);
+        for connection in 1.. {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut reader = BufReader::new(reader);
+
+            for request in 1.. {
+                // Skip the head; none of these have a body.
+                let mut line = String::new();
+                loop {
+                    line.clear();
+                    if reader.read_line(&mut line).await.unwrap() == 0 {
+                        break;
+                    }
+                    if line == "\r\n" {
+                        break;
+                    }
+                }
+                if line.is_empty() {
+                    break;
+                }
+
+                let body = format!("{connection}.{request}");
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{body}",
+                    body.len()
+                );
+                writer.write_all(response.as_bytes()).await.unwrap();
+
+                if connection == 1 && request == 2 {
+                    break;
+                }
+            }
+
+            if let Some(closed) = closed.take() {
+                drop((reader, writer));
+                closed.send(()).unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn keeps_alive_and_retries_a_stale_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let (closed, was_closed) = oneshot::channel();
+        tokio::spawn(serve(listener, closed));
+
+        let client = Client::new();
+        let mut answers = Vec::new();
+        for _ in 0..2 {
+            let mut response = client.get(&url).await.unwrap();
+            answers.push(response.text(16).await.unwrap());
+        }
+
+        // The pooled connection is dead now, which the client only finds out
+        // by sending on it.
+        was_closed.await.unwrap();
+        let mut response = client.get(&url).await.unwrap();
+        answers.push(response.text(16).await.unwrap());
+
+        assert_eq!(answers, ["1.1", "1.2", "2.1"]);
+    }
+}
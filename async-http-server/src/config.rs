@@ -0,0 +1,80 @@
+use std::{fs, path::Path, time::Duration};
+
+use serde::Deserialize;
+
+use crate::{site::SiteConfig, tls::TlsConfig};
+
+/// Everything the server needs to know before it starts accepting
+/// connections. Every field has a default, so an empty (or missing!) config
+/// file gets you the same server as always: plain HTTP on port 7878, serving
+/// the `public` directory.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// The addresses for the plain-HTTP listeners: IPv4 or IPv6 socket
+    /// addresses, or `unix:` followed by a path for a Unix domain socket.
+    pub listen: Vec<String>,
+
+    /// The HTTPS listeners, if there are any.
+    pub tls: Option<TlsConfig>,
+
+    /// The site for requests whose `Host` matches none of `sites`.
+    pub default_site: SiteConfig,
+
+    /// Sites picked by the request's `Host` header.
+    pub sites: Vec<SiteConfig>,
+
+    /// How many worker threads the `ThreadPool` may have.
+    pub workers: WorkersConfig,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            listen: vec![String::from("127.0.0.1:7878")],
+            tls: None,
+            default_site: SiteConfig::default(),
+            sites: Vec::new(),
+            workers: WorkersConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Load the config from a TOML file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Config, String> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path)
+            .map_err(|error| format!("{}: {error}", path.display()))?;
+
+        toml::from_str(&source)
+            .map_err(|error| format!("{}: {error}", path.display()))
+    }
+}
+
+/// The bounds for `ThreadPool::with_limits`.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorkersConfig {
+    pub min: usize,
+    pub max: usize,
+
+    /// How long an idle worker above the minimum waits before retiring.
+    pub keep_alive_secs: u64,
+}
+
+impl WorkersConfig {
+    pub fn keep_alive(&self) -> Duration {
+        Duration::from_secs(self.keep_alive_secs)
+    }
+}
+
+impl Default for WorkersConfig {
+    fn default() -> WorkersConfig {
+        WorkersConfig {
+            min: 4,
+            max: 16,
+            keep_alive_secs: 30,
+        }
+    }
+}
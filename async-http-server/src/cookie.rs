@@ -0,0 +1,239 @@
+use std::{
+    fmt::{self, Display},
+    time::{Duration, SystemTime},
+};
+
+use crate::date::http_date;
+
+/// Parse a `Cookie` request header, like `theme=dark; session=abc123`, into
+/// name-value pairs. Pairs without an `=` are skipped, and quotes around a
+/// value are dropped.
+pub fn parse(header: &str) -> Vec<(String, String)> {
+    header
+        .split(';')
+        .filter_map(|pair| {
+            let (name, value) = pair.split_once('=')?;
+            let name = name.trim();
+            if name.is_empty() {
+                return None;
+            }
+
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|value| value.strip_suffix('"'))
+                .unwrap_or(value);
+
+            Some((name.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+/// Which cross-site requests a cookie gets sent with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+
+    /// Browsers only accept this along with `Secure`.
+    None,
+}
+
+/// A `Set-Cookie` response header, built up a piece at a time, like
+/// `SetCookie::new("theme", "dark").http_only().same_site(SameSite::Lax)`.
+#[derive(Debug, Clone)]
+pub struct SetCookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    max_age: Option<Duration>,
+    expires: Option<SystemTime>,
+    http_only: bool,
+    secure: bool,
+    same_site: Option<SameSite>,
+}
+
+impl SetCookie {
+    /// Any byte of `value` which may not appear in a cookie (spaces, quotes,
+    /// commas, semicolons, backslashes, control characters, and anything
+    /// outside ASCII), and `%` itself, is percent-encoded, so it cannot end
+    /// the header early or add attributes of its own.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` is not a valid cookie name: a non-empty RFC 6265
+    /// token, with no separators, spaces, or control characters.
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> SetCookie {
+        let name = name.into();
+        assert!(is_token(&name), "invalid cookie name {name:?}");
+
+        SetCookie {
+            name,
+            value: encode_value(&value.into()),
+            path: None,
+            domain: None,
+            max_age: None,
+            expires: None,
+            http_only: false,
+            secure: false,
+            same_site: None,
+        }
+    }
+
+    /// A cookie which tells the browser to forget `name` right away.
+    pub fn removal(name: impl Into<String>) -> SetCookie {
+        SetCookie::new(name, "")
+            .max_age(Duration::ZERO)
+            .expires(SystemTime::UNIX_EPOCH)
+    }
+
+    /// # Panics
+    ///
+    /// Panics if `path` has a `;` or a control character in it.
+    pub fn path(mut self, path: impl Into<String>) -> SetCookie {
+        let path = path.into();
+        assert!(is_attribute_value(&path), "invalid cookie path {path:?}");
+        self.path = Some(path);
+        self
+    }
+
+    /// # Panics
+    ///
+    /// Panics if `domain` has a `;` or a control character in it.
+    pub fn domain(mut self, domain: impl Into<String>) -> SetCookie {
+        let domain = domain.into();
+        assert!(
+            is_attribute_value(&domain),
+            "invalid cookie domain {domain:?}"
+        );
+        self.domain = Some(domain);
+        self
+    }
+
+    /// Expire the cookie this long after the browser gets it.
+    pub fn max_age(mut self, max_age: Duration) -> SetCookie {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Expire the cookie at this time. Browsers prefer `max_age` when both
+    /// are set.
+    pub fn expires(mut self, expires: SystemTime) -> SetCookie {
+        self.expires = Some(expires);
+        self
+    }
+
+    /// Keep the cookie away from JavaScript.
+    pub fn http_only(mut self) -> SetCookie {
+        self.http_only = true;
+        self
+    }
+
+    /// Only send the cookie over HTTPS.
+    pub fn secure(mut self) -> SetCookie {
+        self.secure = true;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> SetCookie {
+        self.same_site = Some(same_site);
+        self
+    }
+}
+
+impl Display for SetCookie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+
+        if let Some(path) = &self.path {
+            write!(f, "; Path={path}")?;
+        }
+
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={domain}")?;
+        }
+
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+
+        if let Some(expires) = self.expires {
+            write!(f, "; Expires={}", http_date(expires))?;
+        }
+
+        if self.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+
+        if self.secure {
+            write!(f, "; Secure")?;
+        }
+
+        match self.same_site {
+            Some(SameSite::Strict) => write!(f, "; SameSite=Strict"),
+            Some(SameSite::Lax) => write!(f, "; SameSite=Lax"),
+            Some(SameSite::None) => write!(f, "; SameSite=None"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Whether `name` is an HTTP token (RFC 9110 section 5.6.2), which is what
+/// RFC 6265 allows for a cookie name.
+pub(crate) fn is_token(name: &str) -> bool {
+    !name.is_empty()
+        && name.bytes().all(|byte| {
+            byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte)
+        })
+}
+
+/// Whether `value` can go after an attribute like `Path=` without ending the
+/// attribute early or breaking the header.
+fn is_attribute_value(value: &str) -> bool {
+    !value
+        .bytes()
+        .any(|byte| byte == b';' || byte.is_ascii_control())
+}
+
+/// `value` with every byte RFC 6265 does not allow in a cookie value, plus
+/// `%`, percent-encoded.
+fn encode_value(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        let allowed =
+            matches!(byte, 0x21..=0x7E) && !b"%\",;\\".contains(&byte);
+        if allowed {
+            encoded.push(byte as char);
+        } else {
+            encoded += &format!("%{byte:02X}");
+        }
+    }
+
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_are_encoded() {
+        assert_eq!(encode_value("abc-123_~"), "abc-123_~");
+        assert_eq!(encode_value("a b;c,d"), "a%20b%3Bc%2Cd");
+        assert_eq!(encode_value("100%\"\\"), "100%25%22%5C");
+        assert_eq!(encode_value("é\r\n"), "%C3%A9%0D%0A");
+    }
+
+    #[test]
+    fn tokens() {
+        assert!(is_token("session_id"));
+        assert!(is_token("!#$%&'*+-.^_`|~"));
+        assert!(!is_token(""));
+        assert!(!is_token("a b"));
+        assert!(!is_token("a=b"));
+        assert!(!is_token("a;b"));
+        assert!(!is_token("(a)"));
+        assert!(!is_token("é"));
+    }
+}
@@ -0,0 +1,154 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct",
+    "Nov", "Dec",
+];
+
+/// A point in time broken out into UTC calendar fields.
+struct Civil {
+    year: i64,
+    month: i64,
+    day: i64,
+    hour: i64,
+    minute: i64,
+    second: i64,
+
+    /// Days since Sunday.
+    weekday: i64,
+}
+
+/// Format a time as an RFC 3339 UTC timestamp, like `2024-06-01T12:34:56Z`.
+pub fn rfc3339(time: SystemTime) -> String {
+    let Civil {
+        year,
+        month,
+        day,
+        hour,
+        minute,
+        second,
+        ..
+    } = civil(time);
+
+    format!("{year:04}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}Z")
+}
+
+/// Format a time the way HTTP headers like `Expires` want it, like
+/// `Sat, 01 Jun 2024 12:34:56 GMT`.
+pub fn http_date(time: SystemTime) -> String {
+    let Civil {
+        year,
+        month,
+        day,
+        hour,
+        minute,
+        second,
+        weekday,
+    } = civil(time);
+
+    let weekday = WEEKDAYS[weekday as usize];
+    let month = MONTHS[month as usize - 1];
+    format!(
+        "{weekday}, {day:02} {month} {year:04} \
+         {hour:02}:{minute:02}:{second:02} GMT"
+    )
+}
+
+fn civil(time: SystemTime) -> Civil {
+    let seconds = match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_secs() as i64,
+        Err(before) => -(before.duration().as_secs() as i64),
+    };
+
+    let (days, rest) = (seconds.div_euclid(86_400), seconds.rem_euclid(86_400));
+    let (hour, minute, second) = (rest / 3600, rest % 3600 / 60, rest % 60);
+
+    // The epoch was a Thursday.
+    let weekday = (days + 4).rem_euclid(7);
+
+    // Howard Hinnant's `civil_from_days`: turn a count of days since the epoch
+    // into a proleptic Gregorian calendar date, without any lookup tables.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524
+        - day_of_era / 146_096)
+        / 365;
+    let day_of_year =
+        day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    Civil {
+        year,
+        month,
+        day,
+        hour,
+        minute,
+        second,
+        weekday,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    /// `seconds` from the epoch, either side of it.
+    fn at(seconds: i64) -> SystemTime {
+        let offset = Duration::from_secs(seconds.unsigned_abs());
+        if seconds < 0 {
+            UNIX_EPOCH - offset
+        } else {
+            UNIX_EPOCH + offset
+        }
+    }
+
+    #[test]
+    fn known_dates() {
+        let cases = [
+            (0, "1970-01-01T00:00:00Z", "Thu, 01 Jan 1970 00:00:00 GMT"),
+            (
+                951_827_696,
+                "2000-02-29T12:34:56Z",
+                "Tue, 29 Feb 2000 12:34:56 GMT",
+            ),
+            (
+                1_717_245_296,
+                "2024-06-01T12:34:56Z",
+                "Sat, 01 Jun 2024 12:34:56 GMT",
+            ),
+            (-1, "1969-12-31T23:59:59Z", "Wed, 31 Dec 1969 23:59:59 GMT"),
+            (
+                -2_208_988_800,
+                "1900-01-01T00:00:00Z",
+                "Mon, 01 Jan 1900 00:00:00 GMT",
+            ),
+        ];
+
+        for (seconds, rfc, http) in cases {
+            assert_eq!(rfc3339(at(seconds)), rfc, "{seconds}");
+            assert_eq!(http_date(at(seconds)), http, "{seconds}");
+        }
+    }
+
+    #[test]
+    fn weekdays_follow_each_other() {
+        // The week around the epoch, starting on a Sunday.
+        let start = -4 * 86_400;
+        for (offset, weekday) in WEEKDAYS.iter().enumerate() {
+            let date = http_date(at(start + offset as i64 * 86_400));
+            assert!(date.starts_with(weekday), "{date}");
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use std::sync::Arc;
+
+use futures::{
+    future::{self, BoxFuture},
+    FutureExt,
+};
+use serde::de::DeserializeOwned;
+
+use crate::{
+    http::{Request, Response},
+    site::Site,
+};
+
+/// The largest form or JSON body we will read into memory to deserialize.
+pub const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+/// Deserialize the query string, answering `400` if it does not fit `T`. A
+/// request with no query string at all is treated as an empty one.
+pub fn query<T: DeserializeOwned>(
+    request: &Request<'_>,
+) -> Result<T, Response> {
+    let query = request
+        .target
+        .split_once('?')
+        .map_or("", |(_path, query)| query);
+
+    serde_urlencoded::from_str(query).map_err(|error| {
+        Response::new(400).with_body(format!("Bad query string: {error}\n"))
+    })
+}
+
+/// Read and deserialize an `application/x-www-form-urlencoded` body. Anything
+/// else gets `415`, and a body which does not fit `T` gets `400`.
+pub async fn form<T: DeserializeOwned>(
+    request: &mut Request<'_>,
+) -> Result<T, Response> {
+    require_content_type(request, |media_type| {
+        media_type == "application/x-www-form-urlencoded"
+    })?;
+
+    let body = read_body(request).await?;
+    serde_urlencoded::from_bytes(&body).map_err(|error| {
+        Response::new(400).with_body(format!("Bad form body: {error}\n"))
+    })
+}
+
+/// Read and deserialize a JSON body: `application/json`, or any `+json` type.
+/// Anything else gets `415`, and a body which does not fit `T` gets `400`.
+pub async fn json<T: DeserializeOwned>(
+    request: &mut Request<'_>,
+) -> Result<T, Response> {
+    require_content_type(request, |media_type| {
+        media_type == "application/json" || media_type.ends_with("+json")
+    })?;
+
+    let body = read_body(request).await?;
+    serde_json::from_slice(&body).map_err(|error| {
+        Response::new(400).with_body(format!("Bad JSON body: {error}\n"))
+    })
+}
+
+/// Turn a handler which wants the query string as a `T` into an ordinary one,
+/// to pass to `Site::route`.
+pub fn with_query<T, H>(
+    handler: H,
+) -> impl for<'r> Fn(&'r Site, Request<'r>) -> BoxFuture<'r, Response>
+       + Send
+       + Sync
+       + 'static
+where
+    T: DeserializeOwned + Send + 'static,
+    H: for<'r> Fn(&'r Site, Request<'r>, T) -> BoxFuture<'r, Response>
+        + Send
+        + Sync
+        + 'static,
+{
+    move |site, request| match query(&request) {
+        Ok(value) => handler(site, request, value),
+        Err(response) => future::ready(response).boxed(),
+    }
+}
+
+/// Turn a handler which wants a form body as a `T` into an ordinary one, to
+/// pass to `Site::route`.
+pub fn with_form<T, H>(
+    handler: H,
+) -> impl for<'r> Fn(&'r Site, Request<'r>) -> BoxFuture<'r, Response>
+       + Send
+       + Sync
+       + 'static
+where
+    T: DeserializeOwned + Send + 'static,
+    H: for<'r> Fn(&'r Site, Request<'r>, T) -> BoxFuture<'r, Response>
+        + Send
+        + Sync
+        + 'static,
+{
+    let handler = Arc::new(handler);
+    move |site, mut request| {
+        let handler = Arc::clone(&handler);
+        async move {
+            match form(&mut request).await {
+                Ok(value) => handler(site, request, value).await,
+                Err(response) => response,
+            }
+        }
+        .boxed()
+    }
+}
+
+/// Turn a handler which wants a JSON body as a `T` into an ordinary one, to
+/// pass to `Site::route`.
+pub fn with_json<T, H>(
+    handler: H,
+) -> impl for<'r> Fn(&'r Site, Request<'r>) -> BoxFuture<'r, Response>
+       + Send
+       + Sync
+       + 'static
+where
+    T: DeserializeOwned + Send + 'static,
+    H: for<'r> Fn(&'r Site, Request<'r>, T) -> BoxFuture<'r, Response>
+        + Send
+        + Sync
+        + 'static,
+{
+    let handler = Arc::new(handler);
+    move |site, mut request| {
+        let handler = Arc::clone(&handler);
+        async move {
+            match json(&mut request).await {
+                Ok(value) => handler(site, request, value).await,
+                Err(response) => response,
+            }
+        }
+        .boxed()
+    }
+}
+
+fn require_content_type(
+    request: &Request<'_>,
+    accepts: impl Fn(&str) -> bool,
+) -> Result<(), Response> {
+    let content_type = request.headers.get("Content-Type").unwrap_or("");
+    let media_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+
+    if accepts(&media_type) {
+        return Ok(());
+    }
+
+    Err(Response::new(415)
+        .with_body(format!("Unsupported Content-Type: {content_type}\n")))
+}
+
+/// The whole body, or `413` if it says up front that it is too big, or `400`
+/// if it turns out to be too big or cannot be read.
+async fn read_body(request: &mut Request<'_>) -> Result<Vec<u8>, Response> {
+    let declared = request
+        .headers
+        .get("Content-Length")
+        .and_then(|length| length.parse::<usize>().ok());
+
+    if declared.is_some_and(|length| length > MAX_BODY_SIZE) {
+        return Err(Response::new(413)
+            .with_body(format!("Body larger than {MAX_BODY_SIZE} bytes\n")));
+    }
+
+    request
+        .body
+        .to_vec(MAX_BODY_SIZE)
+        .await
+        .map_err(|error| Response::new(400).with_body(format!("{error}\n")))
+}
@@ -0,0 +1,367 @@
+use std::io;
+
+use serde::Serialize;
+
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt,
+};
+
+use crate::{
+    body::Body,
+    cookie::{self, SetCookie},
+    listener::Peer,
+};
+
+/// The longest request line or header line we are willing to buffer.
+const MAX_LINE_LENGTH: u64 = 8 * 1024;
+
+/// The most headers we are willing to accept on a single request.
+const MAX_HEADERS: usize = 100;
+
+/// Anything a `Request` can read from: the buffered read half of a connection.
+pub type Reader<'a> = dyn AsyncBufRead + Send + Unpin + 'a;
+
+/// A parsed request head, plus its body, which has *not* been read yet: it
+/// borrows the connection and reads from it only as the handler asks for more.
+pub struct Request<'a> {
+    pub method: String,
+    pub target: String,
+    pub version: String,
+    pub headers: Headers,
+    pub peer: Peer,
+    pub body: Body<'a>,
+}
+
+impl<'a> Request<'a> {
+    /// Read the request line and headers from the connection. Returns `None`
+    /// if the client closed the connection before sending anything.
+    pub async fn read(
+        reader: &'a mut Reader<'a>,
+        peer: Peer,
+    ) -> io::Result<Option<Request<'a>>> {
+        let Some(request_line) = read_line(reader).await? else {
+            return Ok(None);
+        };
+
+        let mut parts = request_line.split(' ');
+        let (Some(method), Some(target), Some(version), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid(format!("bad request line: {request_line}")));
+        };
+
+        let (method, target, version) =
+            (method.to_string(), target.to_string(), version.to_string());
+
+        let headers = Headers::read(reader).await?;
+        let body = Body::new(reader, &headers)?;
+
+        Ok(Some(Request {
+            method,
+            target,
+            version,
+            headers,
+            peer,
+            body,
+        }))
+    }
+
+    /// The path part of the target, without any query string.
+    pub fn path(&self) -> &str {
+        self.target
+            .split_once('?')
+            .map_or(self.target.as_str(), |(path, _query)| path)
+    }
+
+    /// Every cookie the client sent, across all its `Cookie` headers.
+    pub fn cookies(&self) -> Vec<(String, String)> {
+        self.headers
+            .get_all("Cookie")
+            .flat_map(cookie::parse)
+            .collect()
+    }
+
+    /// The value of the cookie called `name`, if the client sent one.
+    pub fn cookie(&self, name: &str) -> Option<String> {
+        self.cookies()
+            .into_iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value)
+    }
+}
+
+/// A complete response, ready to write out. Unlike a `Request` body, the
+/// response body is already fully in memory.
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16) -> Response {
+        Response {
+            status,
+            headers: Headers::new(),
+            body: Vec::new(),
+        }
+    }
+
+    /// A 200 response with `value` serialized as its JSON body, or a 500 if
+    /// `value` cannot be serialized.
+    pub fn json<T: Serialize + ?Sized>(value: &T) -> Response {
+        match serde_json::to_vec(value) {
+            Ok(body) => Response::new(200)
+                .with_header("Content-Type", "application/json")
+                .with_body(body),
+            Err(error) => {
+                eprintln!("Could not serialize response: {error}");
+                Response::new(500).with_body("Internal Server Error\n")
+            }
+        }
+    }
+
+    pub fn with_header(
+        mut self,
+        name: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Response {
+        self.headers.append(name, value);
+        self
+    }
+
+    pub fn with_cookie(self, cookie: &SetCookie) -> Response {
+        self.with_header("Set-Cookie", cookie.to_string())
+    }
+
+    pub fn with_body(mut self, body: impl Into<Vec<u8>>) -> Response {
+        self.body = body.into();
+        self
+    }
+
+    /// Write the status line, headers, and body. `Content-Length` comes from
+    /// the body, so do not set it by hand.
+    pub async fn write_to<W>(&self, writer: &mut W) -> io::Result<()>
+    where
+        W: AsyncWrite + Unpin + ?Sized,
+    {
+        self.write_head_to(writer).await?;
+        if !is_bodiless(self.status) {
+            writer.write_all(&self.body).await?;
+        }
+        writer.flush().await
+    }
+
+    /// Write everything but the body, as the answer to a `HEAD` request: the
+    /// `Content-Length` is still that of the body, which is just not sent.
+    pub async fn write_head_to<W>(&self, writer: &mut W) -> io::Result<()>
+    where
+        W: AsyncWrite + Unpin + ?Sized,
+    {
+        let mut head = format!(
+            "HTTP/1.1 {} {}\r\n",
+            self.status,
+            reason_phrase(self.status)
+        );
+
+        for (name, value) in self.headers.iter() {
+            head += &format!("{name}: {value}\r\n");
+        }
+
+        // These never have a body, so a length would only confuse things.
+        if !is_bodiless(self.status) {
+            head += &format!("Content-Length: {}\r\n", self.body.len());
+        }
+        head += "\r\n";
+
+        writer.write_all(head.as_bytes()).await?;
+        writer.flush().await
+    }
+}
+
+/// Whether responses with this status never have a body: informational ones,
+/// `204 No Content` and `304 Not Modified`.
+pub(crate) fn is_bodiless(status: u16) -> bool {
+    (100..200).contains(&status) || status == 204 || status == 304
+}
+
+pub fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Content Too Large",
+        415 => "Unsupported Media Type",
+        500 => "Internal Server Error",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+/// Header names and values, in the order they arrived. Lookups ignore case,
+/// as HTTP requires for names.
+#[derive(Debug, Clone, Default)]
+pub struct Headers(Vec<(String, String)>);
+
+impl Headers {
+    pub fn new() -> Headers {
+        Headers(Vec::new())
+    }
+
+    /// The first value for `name`, if there is one.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn get_all<'h>(
+        &'h self,
+        name: &'h str,
+    ) -> impl Iterator<Item = &'h str> + 'h {
+        self.0
+            .iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn append(
+        &mut self,
+        name: impl Into<String>,
+        value: impl Into<String>,
+    ) {
+        self.0.push((name.into(), value.into()));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    /// Read header lines up to and including the blank line which ends them.
+    pub(crate) async fn read(reader: &mut Reader<'_>) -> io::Result<Headers> {
+        let mut headers = Headers::new();
+
+        loop {
+            let line = read_line(reader).await?.ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "connection closed in the middle of the headers",
+                )
+            })?;
+
+            if line.is_empty() {
+                return Ok(headers);
+            }
+
+            if headers.0.len() >= MAX_HEADERS {
+                return Err(invalid("too many headers"));
+            }
+
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| invalid(format!("bad header line: {line}")))?;
+
+            headers.append(name.trim(), value.trim());
+        }
+    }
+}
+
+/// Read one CRLF- (or bare LF-) terminated line, without the terminator.
+/// Returns `None` at the end of the stream.
+pub(crate) async fn read_line(
+    reader: &mut Reader<'_>,
+) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    let mut limited = (&mut *reader).take(MAX_LINE_LENGTH);
+    let read = limited.read_line(&mut line).await?;
+    if read == 0 {
+        return Ok(None);
+    }
+
+    if !line.ends_with('\n') {
+        return Err(invalid("line too long"));
+    }
+
+    let trimmed = line.trim_end_matches(['\r', '\n']).len();
+    line.truncate(trimmed);
+    Ok(Some(line))
+}
+
+/// Decode `%XX` escapes. Returns `None` if an escape is malformed or the
+/// result is not UTF-8.
+pub fn percent_decode(input: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(input.len());
+    let mut rest = input.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+
+    String::from_utf8(bytes).ok()
+}
+
+/// Escape everything except the unreserved characters, so `text` can go in a
+/// single path segment or query-string value.
+pub fn percent_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z'
+            | b'a'..=b'z'
+            | b'0'..=b'9'
+            | b'-'
+            | b'.'
+            | b'_'
+            | b'~' => encoded.push(byte as char),
+            _ => encoded += &format!("%{byte:02X}"),
+        }
+    }
+
+    encoded
+}
+
+pub(crate) fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn written(response: Response) -> String {
+        let mut bytes = Vec::new();
+        response.write_to(&mut bytes).await.unwrap();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[tokio::test]
+    async fn bodiless_statuses_have_no_length() {
+        for status in [101, 204, 304] {
+            let response = Response::new(status).with_body("ignored");
+            let written = written(response).await;
+            assert!(!written.contains("Content-Length"), "{written}");
+            assert!(written.ends_with("\r\n\r\n"), "{written}");
+        }
+
+        let written = written(Response::new(200).with_body("hello")).await;
+        assert!(written.ends_with("Content-Length: 5\r\n\r\nhello"));
+    }
+}
@@ -1,16 +1,54 @@
+pub mod auth;
+pub mod blocking;
+pub mod body;
+pub mod client;
+pub mod config;
+pub mod cookie;
+pub mod date;
+pub mod extract;
+pub mod http;
+pub mod listener;
+pub mod listing;
+pub mod multipart;
+pub mod session;
+pub mod site;
+pub mod tls;
+
 use std::{
     future::Future,
+    panic::{self, AssertUnwindSafe},
     pin::Pin,
-    sync::{mpsc, Arc, Mutex},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex,
+    },
     thread,
+    time::Duration,
 };
 
-use futures::{executor::LocalPool, task::LocalSpawnExt};
-use tokio::task;
+use serde::Serialize;
+use tokio::runtime::Handle;
+
+use crate::blocking::{BlockingHandle, BlockingPool};
+
+/// How long an extra worker waits for a job before retiring, for pools made
+/// with `ThreadPool::new`, which never have extra workers anyway.
+const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(60);
+
+/// The most threads the blocking pool will start.
+const MAX_BLOCKING_THREADS: usize = 64;
+
+/// How long a blocking thread waits for more work before exiting.
+const BLOCKING_KEEP_ALIVE: Duration = Duration::from_secs(10);
 
 pub struct ThreadPool {
-    workers: Vec<Worker>,
+    workers: Mutex<Vec<Worker>>,
     sender: Option<mpsc::Sender<Job>>,
+    receiver: Arc<Mutex<mpsc::Receiver<Job>>>,
+    scaling: Arc<Scaling>,
+    blocking: BlockingPool,
+    runtime: Option<Handle>,
 }
 
 type Job = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
@@ -26,19 +64,70 @@ impl ThreadPool {
     pub fn new(size: usize) -> ThreadPool {
         assert!(size > 0);
 
+        ThreadPool::with_limits(size, size, DEFAULT_KEEP_ALIVE)
+    }
+
+    /// Create a ThreadPool which scales between `min` and `max` threads.
+    ///
+    /// It starts with `min` workers, and starts another (up to `max`) whenever
+    /// a job arrives to find more jobs queued than there are idle workers. A
+    /// worker which goes `keep_alive` without a job retires, as long as that
+    /// leaves at least `min`. Since only one idle worker at a time waits on the
+    /// queue, they retire one per `keep_alive`, not all at once.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max` is zero or `min` is larger than `max`.
+    pub fn with_limits(
+        min: usize,
+        max: usize,
+        keep_alive: Duration,
+    ) -> ThreadPool {
+        assert!(max > 0);
+        assert!(min <= max);
+
         let (sender, receiver) = mpsc::channel();
 
         let receiver = Arc::new(Mutex::new(receiver));
 
-        let mut workers = Vec::with_capacity(size);
+        let scaling = Arc::new(Scaling {
+            min,
+            max,
+            keep_alive,
+            live: AtomicUsize::new(min),
+            idle: AtomicUsize::new(0),
+            queued: AtomicUsize::new(0),
+            started: AtomicUsize::new(min),
+            retired: AtomicUsize::new(0),
+            next_id: AtomicUsize::new(min),
+        });
+
+        // Jobs use Tokio's sockets and timers, which only work inside a
+        // Tokio runtime's context, so workers borrow the context of whichever
+        // runtime made the pool.
+        let runtime = Handle::try_current().ok();
 
-        for id in 0..size {
-            workers.push(Worker::new(id, Arc::clone(&receiver)));
+        let mut workers = Vec::with_capacity(max);
+
+        for id in 0..min {
+            workers.push(Worker::new(
+                id,
+                Arc::clone(&receiver),
+                Arc::clone(&scaling),
+                runtime.clone(),
+            ));
         }
 
         ThreadPool {
-            workers,
+            workers: Mutex::new(workers),
             sender: Some(sender),
+            receiver,
+            scaling,
+            blocking: BlockingPool::new(
+                MAX_BLOCKING_THREADS,
+                BLOCKING_KEEP_ALIVE,
+            ),
+            runtime,
         }
     }
 
@@ -48,15 +137,163 @@ impl ThreadPool {
     {
         let job = Box::pin(f);
 
+        // Count it before sending it, so a worker can never take it off the
+        // queue before it has been counted as being on the queue.
+        self.scaling.queued.fetch_add(1, Ordering::SeqCst);
         self.sender.as_ref().unwrap().send(job).unwrap();
+
+        self.grow_if_backed_up();
+    }
+
+    /// Start another worker if there are more jobs waiting than there are
+    /// workers waiting for jobs, and we are not at the limit.
+    fn grow_if_backed_up(&self) {
+        let queued = self.scaling.queued.load(Ordering::SeqCst);
+        let idle = self.scaling.idle.load(Ordering::SeqCst);
+        if queued <= idle {
+            return;
+        }
+
+        let max = self.scaling.max;
+        let claimed = self.scaling.live.fetch_update(
+            Ordering::SeqCst,
+            Ordering::SeqCst,
+            |live| if live < max { Some(live + 1) } else { None },
+        );
+
+        let Ok(before) = claimed else {
+            return;
+        };
+
+        let id = self.scaling.next_id.fetch_add(1, Ordering::SeqCst);
+        self.scaling.started.fetch_add(1, Ordering::SeqCst);
+        println!(
+            "{queued} jobs queued, {idle} workers idle: starting worker {id} \
+             ({} of at most {max} workers)",
+            before + 1
+        );
+
+        let mut workers = self.workers.lock().unwrap();
+
+        // Workers which retired are done; there is nothing left to join.
+        workers.retain(|worker| {
+            !worker
+                .thread
+                .as_ref()
+                .is_some_and(|thread| thread.is_finished())
+        });
+
+        workers.push(Worker::new(
+            id,
+            Arc::clone(&self.receiver),
+            Arc::clone(&self.scaling),
+            self.runtime.clone(),
+        ));
+    }
+
+    /// A handle for reading how busy the pool is and how it has scaled.
+    pub fn metrics(&self) -> PoolMetrics {
+        PoolMetrics {
+            scaling: Arc::clone(&self.scaling),
+        }
+    }
+
+    /// Run blocking work on a separate set of threads, so it does not stall
+    /// one of the workers, and get back a future for its result.
+    pub fn spawn_blocking<F, T>(&self, f: F) -> BlockingHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.blocking.spawn(f)
+    }
+
+    /// A handle to the blocking threads, for code which needs to call
+    /// `spawn_blocking` but does not have the `ThreadPool` itself.
+    pub fn blocking_pool(&self) -> BlockingPool {
+        self.blocking.clone()
+    }
+}
+
+/// The bounds the pool scales within, and the counts it scales by.
+struct Scaling {
+    min: usize,
+    max: usize,
+    keep_alive: Duration,
+    live: AtomicUsize,
+    idle: AtomicUsize,
+    queued: AtomicUsize,
+    started: AtomicUsize,
+    retired: AtomicUsize,
+    next_id: AtomicUsize,
+}
+
+impl Scaling {
+    /// Claim the right to retire one worker, unless that would leave fewer
+    /// than the minimum.
+    fn try_retire(&self) -> bool {
+        let min = self.min;
+        self.live
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |live| {
+                if live > min {
+                    Some(live - 1)
+                } else {
+                    None
+                }
+            })
+            .is_ok()
+    }
+}
+
+/// A cloneable handle to a `ThreadPool`'s counters.
+#[derive(Clone)]
+pub struct PoolMetrics {
+    scaling: Arc<Scaling>,
+}
+
+impl PoolMetrics {
+    pub fn snapshot(&self) -> PoolStats {
+        let scaling = &self.scaling;
+        PoolStats {
+            workers: scaling.live.load(Ordering::SeqCst),
+            idle: scaling.idle.load(Ordering::SeqCst),
+            queued: scaling.queued.load(Ordering::SeqCst),
+            started: scaling.started.load(Ordering::SeqCst),
+            retired: scaling.retired.load(Ordering::SeqCst),
+            min: scaling.min,
+            max: scaling.max,
+        }
     }
 }
 
+/// How the pool looked at one moment.
+#[derive(Debug, Clone, Serialize)]
+pub struct PoolStats {
+    /// Workers running right now.
+    pub workers: usize,
+
+    /// Workers waiting for a job.
+    pub idle: usize,
+
+    /// Jobs waiting for a worker.
+    pub queued: usize,
+
+    /// Workers started since the pool was created, including the first `min`.
+    pub started: usize,
+
+    /// Workers which retired after going idle.
+    pub retired: usize,
+
+    pub min: usize,
+    pub max: usize,
+}
+
 impl Drop for ThreadPool {
     fn drop(&mut self) {
         drop(self.sender.take());
+        self.blocking.shutdown();
 
-        for worker in &mut self.workers {
+        for worker in self.workers.get_mut().unwrap().iter_mut() {
             println!("Shutting down worker {}", worker.id);
 
             if let Some(thread) = worker.thread.take() {
@@ -72,28 +309,53 @@ struct Worker {
 }
 
 impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Worker {
+    fn new(
+        id: usize,
+        receiver: Arc<Mutex<mpsc::Receiver<Job>>>,
+        scaling: Arc<Scaling>,
+        runtime: Option<Handle>,
+    ) -> Worker {
         let thread = thread::spawn(move || loop {
-            let message = receiver.lock().unwrap().recv();
+            scaling.idle.fetch_add(1, Ordering::SeqCst);
+            let message =
+                receiver.lock().unwrap().recv_timeout(scaling.keep_alive);
+            scaling.idle.fetch_sub(1, Ordering::SeqCst);
 
             match message {
                 Ok(job) => {
+                    scaling.queued.fetch_sub(1, Ordering::SeqCst);
                     println!("Worker {id} got a job; executing.");
-                    // This does not compile, of course: we are not in an async
-                    // context. But the question is: how to *make* it work in an
-                    // async context, but in a way that, you know, makes sense.
-                    // Tokio's runtime does not expose enough hooks to do it,
-                    // and using Tokio's modules requires all of its futures to
-                    // be running in *its* runtime, or they panic!
+                    // We are not in an async context here, so drive the job
+                    // to completion on this thread with a tiny executor of
+                    // our own. Tokio's sockets and timers panic outside of a
+                    // Tokio runtime's context, though, so enter the context of
+                    // the runtime which made the pool first. Its own threads
+                    // keep the I/O driver and timers turning, and wake us.
                     //
-                    // The alternatives are to use `futures::{io, executor}` or
-                    // `smol::{fs, Executor}` to do as much of this as possible,
-                    // but `futures` does not have the relevant tools for the
-                    // network APIs (like `TcpListener`), so we would have to
-                    // teach through building *that*.
-                    job.await;
+                    // A job which panics must not take the worker down with
+                    // it, or the pool would go on counting a thread which no
+                    // longer exists.
+                    let _context = runtime.as_ref().map(Handle::enter);
+                    let ran = panic::catch_unwind(AssertUnwindSafe(|| {
+                        futures::executor::block_on(job)
+                    }));
+                    if ran.is_err() {
+                        eprintln!("Worker {id}'s job panicked.");
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    if scaling.try_retire() {
+                        scaling.retired.fetch_add(1, Ordering::SeqCst);
+                        println!(
+                            "Worker {id} idle for {:?}; retiring ({} workers left)",
+                            scaling.keep_alive,
+                            scaling.live.load(Ordering::SeqCst),
+                        );
+                        break;
+                    }
                 }
-                Err(_) => {
+                Err(RecvTimeoutError::Disconnected) => {
+                    scaling.live.fetch_sub(1, Ordering::SeqCst);
                     println!("Worker {id} disconnected; shutting down.");
                     break;
                 }
@@ -106,3 +368,20 @@ impl Worker {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_panicking_job_leaves_its_worker_running() {
+        let pool = ThreadPool::new(1);
+        pool.execute(async { panic!("job failed") });
+
+        let (sender, receiver) = mpsc::channel();
+        pool.execute(async move { sender.send(()).unwrap() });
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        assert_eq!(pool.metrics().snapshot().workers, 1);
+    }
+}
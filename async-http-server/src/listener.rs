@@ -0,0 +1,200 @@
+use std::{
+    fmt, fs, io,
+    net::SocketAddr,
+    os::unix::fs::FileTypeExt,
+    path::{Path, PathBuf},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{TcpListener, TcpStream, UnixListener, UnixStream},
+};
+
+/// A bound socket, either TCP (IPv4 or IPv6) or a Unix domain socket.
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener, PathBuf),
+}
+
+impl Listener {
+    /// Bind to an address. Anything starting with `unix:` is a Unix domain
+    /// socket path; everything else is a TCP socket address, so IPv6 addresses
+    /// need their brackets, e.g. `[::1]:7878`.
+    pub async fn bind(addr: &str) -> io::Result<Listener> {
+        match addr.strip_prefix("unix:") {
+            Some(path) => {
+                let path = PathBuf::from(path);
+
+                // A socket file left behind by a previous run would make the
+                // bind fail with "address in use", even though nothing is
+                // listening on it anymore. A socket something is still
+                // listening on, or anything else at that path, is not ours to
+                // delete.
+                match fs::symlink_metadata(&path) {
+                    Ok(meta) if meta.file_type().is_socket() => {
+                        remove_stale_socket(&path)?
+                    }
+                    Ok(_) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::AlreadyExists,
+                            format!(
+                                "{} exists and is not a socket",
+                                path.display()
+                            ),
+                        ))
+                    }
+                    Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+                    Err(error) => return Err(error),
+                }
+
+                let listener = UnixListener::bind(&path)?;
+                Ok(Listener::Unix(listener, path))
+            }
+            None => TcpListener::bind(addr).await.map(Listener::Tcp),
+        }
+    }
+
+    pub async fn accept(&self) -> io::Result<(Stream, Peer)> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, addr) = listener.accept().await?;
+
+                // Responses go out as a head and then a body. With Nagle's
+                // algorithm on, the body waits for the client to ACK the head,
+                // which it may delay for tens of milliseconds.
+                stream.set_nodelay(true)?;
+                Ok((Stream::Tcp(stream), Peer::Tcp(addr)))
+            }
+
+            // Clients connecting over a Unix socket are almost always unnamed,
+            // so the socket path we are listening on is the most useful thing
+            // to report for them.
+            Listener::Unix(listener, path) => {
+                let (stream, _addr) = listener.accept().await?;
+                Ok((Stream::Unix(stream), Peer::Unix(path.clone())))
+            }
+        }
+    }
+
+    /// Where this listener is bound, in the same format `bind` accepts.
+    pub fn local_addr(&self) -> String {
+        match self {
+            Listener::Tcp(listener) => listener
+                .local_addr()
+                .map(|addr| addr.to_string())
+                .unwrap_or_else(|error| format!("<unknown: {error}>")),
+            Listener::Unix(_, path) => format!("unix:{}", path.display()),
+        }
+    }
+}
+
+/// Remove a socket file, but only if nothing is listening on it: a connection
+/// refused means whoever bound it is gone.
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    match std::os::unix::net::UnixStream::connect(path) {
+        Err(error) if error.kind() == io::ErrorKind::ConnectionRefused => {
+            fs::remove_file(path)
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("{} is in use", path.display()),
+        )),
+    }
+}
+
+/// Who is on the other end of a connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Peer {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Peer::Tcp(addr) => write!(f, "{addr}"),
+            Peer::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// An accepted connection from any kind of `Listener`.
+pub enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+// Both kinds of stream are `Unpin`, so there is no pin projection to worry
+// about here: just forward to whichever one we have.
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            Stream::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            Stream::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            Stream::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            Stream::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn only_a_stale_socket_is_replaced() {
+        let path = std::env::temp_dir().join(format!(
+            "async-http-server-listener-{}.sock",
+            std::process::id()
+        ));
+        let addr = format!("unix:{}", path.display());
+
+        // A socket something is listening on is left alone.
+        let first = Listener::bind(&addr).await.unwrap();
+        let error = Listener::bind(&addr).await.err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::AddrInUse);
+
+        // Once its listener is gone, it is fair game.
+        drop(first);
+        let second = Listener::bind(&addr).await;
+        fs::remove_file(&path).unwrap();
+        assert!(second.is_ok());
+    }
+}
@@ -0,0 +1,151 @@
+use std::{io, path::Path};
+
+use serde::Serialize;
+use tokio::fs;
+
+use crate::{
+    date::rfc3339,
+    http::{percent_encode, Response},
+};
+
+#[derive(Debug, Serialize)]
+struct Entry {
+    name: String,
+    href: String,
+    directory: bool,
+    size: u64,
+    modified: Option<String>,
+}
+
+/// A listing of the directory at `dir`, which the client asked for as `path`,
+/// already decoded: HTML by default, or JSON if `json` is set. Directories come
+/// first, then files, each sorted by name. Dotfiles, and entries whose
+/// metadata cannot be read, are left out.
+pub async fn render(
+    dir: &Path,
+    path: &str,
+    json: bool,
+) -> io::Result<Response> {
+    // Links are absolute, so they work whether or not the request path ends in
+    // a slash.
+    let base: String = path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| format!("/{}", percent_encode(segment)))
+        .collect();
+
+    let mut entries = Vec::new();
+    let mut read_dir = fs::read_dir(dir).await?;
+    while let Some(dir_entry) = read_dir.next_entry().await? {
+        let name = dir_entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') {
+            continue;
+        }
+
+        // A dangling symlink, or something removed since `read_dir` saw it,
+        // has nothing to show, but should not spoil the rest of the listing.
+        let Ok(metadata) = dir_entry.metadata().await else {
+            continue;
+        };
+        let directory = metadata.is_dir();
+        let slash = if directory { "/" } else { "" };
+
+        entries.push(Entry {
+            href: format!("{base}/{}{slash}", percent_encode(&name)),
+            name,
+            directory,
+            size: if directory { 0 } else { metadata.len() },
+            modified: metadata.modified().ok().map(rfc3339),
+        });
+    }
+
+    entries.sort_by(|a, b| {
+        b.directory
+            .cmp(&a.directory)
+            .then_with(|| a.name.cmp(&b.name))
+    });
+
+    let parent = if base.is_empty() {
+        None
+    } else {
+        let (parent, _) = base.rsplit_once('/').unwrap_or(("", &base));
+        Some(format!("{parent}/"))
+    };
+
+    if json {
+        let body = serde_json::json!({
+            "path": path,
+            "parent": parent,
+            "entries": entries,
+        });
+
+        return Ok(Response::new(200)
+            .with_header("Content-Type", "application/json")
+            .with_header("Vary", "Accept")
+            .with_body(body.to_string()));
+    }
+
+    let title = escape(&format!("Index of {path}"));
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>{title}</title>\n</head>\n<body>\n<h1>{title}</h1>\n\
+         <table>\n<tr><th>Name</th><th>Size</th><th>Modified</th></tr>\n"
+    );
+
+    if let Some(parent) = parent {
+        let href = escape(&parent);
+        html += &format!(
+            "<tr><td><a href=\"{href}\">../</a></td><td></td><td></td></tr>\n"
+        );
+    }
+
+    for entry in &entries {
+        let slash = if entry.directory { "/" } else { "" };
+        let size = if entry.directory {
+            String::from("-")
+        } else {
+            entry.size.to_string()
+        };
+
+        let href = escape(&entry.href);
+        let name = escape(&entry.name);
+        let modified = entry.modified.as_deref().unwrap_or("");
+        html += &format!(
+            "<tr><td><a href=\"{href}\">{name}{slash}</a></td>\
+             <td>{size}</td><td>{modified}</td></tr>\n"
+        );
+    }
+
+    html += "</table>\n</body>\n</html>\n";
+
+    Ok(Response::new(200)
+        .with_header("Content-Type", "text/html; charset=utf-8")
+        .with_header("Vary", "Accept")
+        .with_body(html))
+}
+
+/// Whether an `Accept` header prefers JSON to HTML: whichever is mentioned
+/// first wins, and HTML wins if neither is.
+pub fn wants_json(accept: Option<&str>) -> bool {
+    let Some(accept) = accept else {
+        return false;
+    };
+
+    for media_type in accept.split(',') {
+        let media_type = media_type.split(';').next().unwrap_or("").trim();
+        match media_type {
+            "application/json" => return true,
+            "text/html" => return false,
+            _ => {}
+        }
+    }
+
+    false
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
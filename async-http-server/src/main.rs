@@ -1,79 +1,553 @@
-use std::time::Duration;
+use std::{
+    path::Path,
+    sync::Arc,
+    thread,
+    time::{Duration, SystemTime},
+};
 
-use async_http_server::ThreadPool;
+use async_http_server::{
+    blocking::BlockingPool,
+    body::Body,
+    config::Config,
+    date, extract,
+    http::{Request, Response},
+    listener::{Listener, Peer},
+    multipart::Multipart,
+    session::{self, MemoryStore, Sessions},
+    site::{Site, Sites},
+    tls, PoolMetrics, ThreadPool,
+};
+use futures::{future, stream::FuturesOrdered, FutureExt, StreamExt};
+use serde::Deserialize;
 use tokio::{
     fs,
-    io::{AsyncBufRead, AsyncBufReadExt, AsyncWriteExt, BufReader},
-    net::{TcpListener, TcpStream},
+    io::{self, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
     time,
 };
-use tokio_stream::{
-    wrappers::{LinesStream, TcpListenerStream},
-    StreamExt,
-};
+use tokio_rustls::TlsAcceptor;
+
+/// How long to wait for the next request on a kept-alive connection.
+const KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The most pipelined requests we will work on at once for one connection.
+/// Past this, the rest wait in the socket until earlier ones are answered.
+const MAX_PIPELINED: usize = 16;
+
+/// How long a client gets to finish the TLS handshake before it is dropped.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait before accepting again after an accept fails.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
 
 #[tokio::main]
 async fn main() {
-    let mut listener = TcpListener::bind("127.0.0.1:7878")
-        .await
-        .unwrap()
-        .to_stream();
-
-    let pool = ThreadPool::new(4);
-
-    while let Some(stream) = listener.next().await {
-        let stream = stream.unwrap();
-        pool.execute(async {
-            println!("Executing task");
-            handle_connection(stream).await;
-        });
+    let config = match std::env::args().nth(1) {
+        Some(path) => Config::load(path).unwrap(),
+        None => Config::default(),
+    };
+
+    let workers = &config.workers;
+    let pool =
+        ThreadPool::with_limits(workers.min, workers.max, workers.keep_alive());
+
+    let default_site =
+        Site::from_config(&config.default_site, &pool.blocking_pool()).unwrap();
+    // A fresh secret each run means everyone's sessions end with the server,
+    // which for a demo is fine.
+    let sessions = Sessions::new(MemoryStore::new(), &session::random_secret());
+    let default_site =
+        routes(default_site, pool.blocking_pool(), pool.metrics(), sessions);
+    let mut sites = Sites::new(default_site);
+    for site_config in &config.sites {
+        let site =
+            Site::from_config(site_config, &pool.blocking_pool()).unwrap();
+        sites.add(&site_config.hosts, site);
     }
+    let sites = Arc::new(sites);
+
+    let mut listeners = Vec::new();
+    for addr in &config.listen {
+        listeners.push((Listener::bind(addr).await.unwrap(), None));
+    }
+
+    if let Some(tls_config) = &config.tls {
+        let acceptor = tls::acceptor(tls_config).unwrap();
+        for addr in &tls_config.listen {
+            let listener = Listener::bind(addr).await.unwrap();
+            listeners.push((listener, Some(acceptor.clone())));
+        }
+    }
+
+    // Every listener feeds the same pool, so one accept loop per listener is
+    // all it takes; none of them ever finishes unless accepting itself does.
+    future::join_all(
+        listeners.into_iter().map(|(listener, acceptor)| {
+            serve(listener, acceptor, &sites, &pool)
+        }),
+    )
+    .await;
 }
 
-async fn handle_connection(mut stream: TcpStream) {
-    let buf_reader = BufReader::new(&mut stream);
-    let request_line = buf_reader.lines_stream().next().await.unwrap().unwrap();
+async fn serve(
+    listener: Listener,
+    acceptor: Option<TlsAcceptor>,
+    sites: &Arc<Sites>,
+    pool: &ThreadPool,
+) {
+    let scheme = if acceptor.is_some() { "https" } else { "http" };
+    println!("Listening for {scheme} on {}", listener.local_addr());
 
-    let (status_line, file_name) = match request_line.as_str() {
-        "GET / HTTP/1.1" => ("HTTP/1.1 200 OK", "hello.html"),
-        "GET /sleep HTTP/1.1" => {
-            time::sleep(Duration::from_secs(5)).await;
-            ("HTTP/1.1 200 OK", "hello.html")
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(error) => {
+                eprintln!(
+                    "Accept failed on {}: {error}",
+                    listener.local_addr()
+                );
+
+                // Usually out of file descriptors, which will not clear up
+                // straight away; retrying at once would just spin.
+                time::sleep(ACCEPT_BACKOFF).await;
+                continue;
+            }
+        };
+
+        let sites = Arc::clone(sites);
+        let metrics = pool.metrics();
+        match acceptor.clone() {
+            None => pool.execute(async {
+                println!("Executing task for {peer}");
+                handle_connection(stream, peer, sites, metrics).await;
+            }),
+
+            // Do the handshake on the worker, not here, so one slow client
+            // cannot hold up accepting everyone else. It still holds up the
+            // worker, though, so give up on a client which never finishes.
+            Some(acceptor) => pool.execute(async move {
+                println!("Executing TLS task for {peer}");
+                let handshake =
+                    time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream));
+                match handshake.await {
+                    Ok(Ok(stream)) => {
+                        handle_connection(stream, peer, sites, metrics).await
+                    }
+                    Ok(Err(error)) => {
+                        eprintln!("TLS handshake with {peer} failed: {error}")
+                    }
+                    Err(_) => eprintln!("TLS handshake with {peer} timed out"),
+                }
+            }),
         }
-        _ => ("HTTP/1.1 404 NOT FOUND", "404.html"),
-    };
+    }
+}
+
+/// A response on its way back, and how to send it.
+struct Answer {
+    response: Response,
+
+    /// The version of the request being answered.
+    version: String,
+    head: bool,
+    keep_alive: bool,
+}
+
+async fn handle_connection<S>(
+    stream: S,
+    peer: Peer,
+    sites: Arc<Sites>,
+    metrics: PoolMetrics,
+) where
+    S: AsyncRead + AsyncWrite + Send + Unpin,
+{
+    let (reader, mut writer) = io::split(stream);
+    let mut reader = BufReader::new(reader);
+
+    // Answers to requests the client pipelined, oldest first. They only make
+    // progress while we wait on `pending`, so we never wait on the client
+    // while any are outstanding, and they go out strictly in the order the
+    // requests came.
+    let mut pending = FuturesOrdered::new();
+    let mut reading = true;
+    let mut answered_any = false;
+
+    loop {
+        // With nothing outstanding, wait for the next request. Otherwise, only
+        // read ahead if the client has already sent the whole of the next
+        // request's head, so reading it cannot block, and only so far.
+        while reading
+            && pending.len() < MAX_PIPELINED
+            && (pending.is_empty() || head_buffered(reader.buffer()))
+        {
+            // A connection holds its worker for as long as it stays open, so
+            // once other connections are queued up waiting for one, hand it
+            // back between requests. The client can reconnect, and takes its
+            // turn behind them.
+            if pending.is_empty()
+                && answered_any
+                && !head_buffered(reader.buffer())
+                && metrics.snapshot().queued > 0
+            {
+                return;
+            }
 
-    let contents = fs::read_to_string(file_name).await.unwrap();
-    let length = contents.len();
+            // Do not let an idle kept-alive connection, or one which trickles
+            // in a request head, tie up a worker forever.
+            let read = Request::read(&mut reader, peer.clone());
+            let read = if pending.is_empty() {
+                match time::timeout(KEEP_ALIVE_TIMEOUT, read).await {
+                    Ok(read) => read,
+                    Err(_) => return,
+                }
+            } else {
+                read.await
+            };
+
+            let request = match read {
+                Ok(Some(request)) => request,
+                Ok(None) => {
+                    reading = false;
+                    break;
+                }
+                Err(error) => {
+                    eprintln!("{peer}: bad request: {error}");
+                    let response =
+                        Response::new(400).with_body(format!("{error}\n"));
+                    pending.push_back(
+                        future::ready(Answer {
+                            response,
+                            version: String::from("HTTP/1.1"),
+                            head: false,
+                            keep_alive: false,
+                        })
+                        .boxed(),
+                    );
+                    reading = false;
+                    break;
+                }
+            };
+
+            println!(
+                "{peer}: {} {} {}",
+                request.method, request.target, request.version
+            );
+
+            let version = request.version.clone();
+            let head = request.method == "HEAD";
+            let keep_alive = keep_alive(&request);
+
+            // A handler reads the body straight off the connection, so a
+            // request with one has to wait until everything before it has
+            // been answered, and nothing after it can be read until it is.
+            if has_body(&request) {
+                while let Some(answer) = pending.next().await {
+                    if !send(&mut writer, &peer, answer).await {
+                        return;
+                    }
+                }
+
+                // The client is holding the body back until we say so, which
+                // we only do once a handler actually asks for it.
+                let mut request = request;
+                if expects_continue(&request) {
+                    let writer = &mut writer;
+                    request.body.before_reading(async move {
+                        writer
+                            .write_all(b"HTTP/1.1 100 Continue\r\n\r\n")
+                            .await?;
+                        writer.flush().await
+                    });
+                }
+
+                let response = sites.respond(request).await;
+                let answer = Answer {
+                    response,
+                    version,
+                    head,
+                    keep_alive,
+                };
+                if !send(&mut writer, &peer, answer).await {
+                    return;
+                }
+                answered_any = true;
+                continue;
+            }
+
+            // No body means the request no longer needs the connection, so it
+            // can be handled alongside the others.
+            let request = Request {
+                method: request.method,
+                target: request.target,
+                version: request.version,
+                headers: request.headers,
+                peer: request.peer,
+                body: Body::empty(),
+            };
+
+            let sites = Arc::clone(&sites);
+            pending.push_back(
+                async move {
+                    Answer {
+                        response: sites.respond(request).await,
+                        version,
+                        head,
+                        keep_alive,
+                    }
+                }
+                .boxed(),
+            );
+
+            if !keep_alive {
+                reading = false;
+            }
+        }
 
-    let response =
-        format!("{status_line}\r\nContent-Length: {length}\r\n\r\n{contents}");
+        let Some(answer) = pending.next().await else {
+            return;
+        };
 
-    stream.write_all(response.as_bytes()).await.unwrap();
+        if !send(&mut writer, &peer, answer).await {
+            return;
+        }
+        answered_any = true;
+    }
 }
 
-trait ToListenerStream {
-    fn to_stream(self) -> TcpListenerStream;
+/// Write out an answer. Returns whether the connection should stay open.
+async fn send<W>(writer: &mut W, peer: &Peer, answer: Answer) -> bool
+where
+    W: AsyncWrite + Unpin,
+{
+    let Answer {
+        response,
+        version,
+        head,
+        keep_alive,
+    } = answer;
+
+    // HTTP/1.1 clients assume keep-alive, but an HTTP/1.0 one closes the
+    // connection after the response unless told we are keeping it open.
+    let response = match (keep_alive, version.as_str()) {
+        (false, _) => response.with_header("Connection", "close"),
+        (true, "HTTP/1.1") => response,
+        (true, _) => response.with_header("Connection", "keep-alive"),
+    };
+
+    let written = if head {
+        response.write_head_to(writer).await
+    } else {
+        response.write_to(writer).await
+    };
+
+    if let Err(error) = written {
+        eprintln!("{peer}: could not write response: {error}");
+        return false;
+    }
+
+    keep_alive
 }
 
-impl ToListenerStream for TcpListener {
-    fn to_stream(self) -> TcpListenerStream {
-        TcpListenerStream::new(self)
+/// Whether to wait for another request on the same connection after this
+/// one. HTTP/1.1 keeps connections alive unless asked not to, and HTTP/1.0
+/// only if asked to.
+///
+/// A request with a body closes the connection regardless: the handler may
+/// not have read all of it, and then there is no telling where the next
+/// request starts.
+fn keep_alive(request: &Request<'_>) -> bool {
+    if has_body(request) {
+        return false;
+    }
+
+    let connection = |option: &str| {
+        request.headers.get_all("Connection").any(|value| {
+            value
+                .split(',')
+                .any(|value| value.trim().eq_ignore_ascii_case(option))
+        })
+    };
+
+    match request.version.as_str() {
+        "HTTP/1.1" => !connection("close"),
+        _ => connection("keep-alive"),
     }
 }
 
-// MAYBE: support code in `trpl` so people can just call `.lines_stream()` To
-// make it work, we would need to have users import it *or* have them import a
-// `prelude`, like `use trpl::{Something, prelude::*}`?
-trait ToLinesStream<R> {
-    fn lines_stream(self) -> LinesStream<R>;
+/// Whether `buffer` holds a request head right through to the blank line
+/// which ends it.
+fn head_buffered(buffer: &[u8]) -> bool {
+    buffer.windows(2).any(|window| window == b"\n\n")
+        || buffer.windows(3).any(|window| window == b"\n\r\n")
 }
 
-impl<R> ToLinesStream<R> for R
-where
-    R: AsyncBufRead,
-{
-    fn lines_stream(self) -> LinesStream<R> {
-        LinesStream::new(self.lines())
+fn has_body(request: &Request<'_>) -> bool {
+    let headers = &request.headers;
+    headers.get("Transfer-Encoding").is_some()
+        || headers
+            .get("Content-Length")
+            .is_some_and(|length| length.trim() != "0")
+}
+
+/// Whether the client will wait for a `100 Continue` before sending the body.
+/// Only HTTP/1.1 clients know to expect one.
+fn expects_continue(request: &Request<'_>) -> bool {
+    request.version == "HTTP/1.1"
+        && request
+            .headers
+            .get_all("Expect")
+            .any(|value| value.trim().eq_ignore_ascii_case("100-continue"))
+}
+
+/// The demo routes: the ones which need code, not just a file from the root.
+fn routes(
+    site: Site,
+    blocking: BlockingPool,
+    metrics: PoolMetrics,
+    sessions: Sessions,
+) -> Site {
+    site.route("GET", "/", |site, _request| {
+        site.page(200, "hello.html").boxed()
+    })
+    .route("GET", "/sleep", |site, _request| {
+        async move {
+            time::sleep(Duration::from_secs(5)).await;
+            site.page(200, "hello.html").await
+        }
+        .boxed()
+    })
+    // The same, but with a sleep which blocks its whole thread. On a worker,
+    // this would hold up every other connection there; on the blocking pool,
+    // it holds up nothing.
+    .route("GET", "/sleep-blocking", move |site, _request| {
+        let slept = blocking.spawn(|| thread::sleep(Duration::from_secs(5)));
+        async move {
+            match slept.await {
+                Ok(()) => site.page(200, "hello.html").await,
+                Err(_) => Response::new(500).with_body("Could not sleep\n"),
+            }
+        }
+        .boxed()
+    })
+    .route("GET", "/stats", move |_site, _request| {
+        future::ready(Response::json(&metrics.snapshot())).boxed()
+    })
+    .route(
+        "GET",
+        "/visits",
+        sessions.wrap(|_site, _request, session| {
+            let visits = session
+                .get("visits")
+                .and_then(|visits| visits.parse::<u64>().ok())
+                .unwrap_or(0)
+                + 1;
+            session.insert("visits", visits.to_string());
+
+            let body = format!("You have been here {visits} time(s).\n");
+            future::ready(Response::new(200).with_body(body)).boxed()
+        }),
+    )
+    .route(
+        "POST",
+        "/logout",
+        sessions.wrap(|_site, _request, session| {
+            session.destroy();
+            future::ready(Response::new(204)).boxed()
+        }),
+    )
+    .route(
+        "GET",
+        "/greet",
+        extract::with_query(|_site, _request, greeting: Greeting| {
+            future::ready(greet(greeting)).boxed()
+        }),
+    )
+    .route(
+        "POST",
+        "/greet",
+        extract::with_form(|_site, _request, greeting: Greeting| {
+            future::ready(greet(greeting)).boxed()
+        }),
+    )
+    // Hand back whatever JSON object came in, plus when it arrived.
+    .route(
+        "POST",
+        "/echo",
+        extract::with_json(|_site, _request, mut value: serde_json::Value| {
+            if let Some(object) = value.as_object_mut() {
+                object.insert(
+                    "received".into(),
+                    date::rfc3339(SystemTime::now()).into(),
+                );
+            }
+            future::ready(Response::json(&value)).boxed()
+        }),
+    )
+    .route("POST", "/upload", |_site, request| {
+        async move {
+            match upload(request).await {
+                Ok(summary) => Response::new(200).with_body(summary),
+                Err(error) => {
+                    Response::new(400).with_body(format!("{error}\n"))
+                }
+            }
+        }
+        .boxed()
+    })
+}
+
+#[derive(Deserialize)]
+struct Greeting {
+    name: String,
+    #[serde(default = "one")]
+    times: usize,
+}
+
+fn one() -> usize {
+    1
+}
+
+fn greet(greeting: Greeting) -> Response {
+    let times = greeting.times.min(100);
+    Response::new(200)
+        .with_body(format!("Hello, {}!\n", greeting.name).repeat(times))
+}
+
+/// Save every file in a `multipart/form-data` upload into `uploads/`, a chunk
+/// at a time as it arrives, and report what came in.
+async fn upload(request: Request<'_>) -> io::Result<String> {
+    let mut multipart = Multipart::from_request(request)?;
+    fs::create_dir_all("uploads").await?;
+
+    let mut summary = String::new();
+    while let Some(mut field) = multipart.next_field().await? {
+        let name = field.name().unwrap_or("<unnamed>").to_string();
+
+        // The client picks the file name, so only keep its last component:
+        // nobody gets to upload to `../../etc/passwd`.
+        let file_name = field
+            .file_name()
+            .and_then(|file_name| Path::new(file_name).file_name())
+            .map(|file_name| file_name.to_owned());
+
+        match file_name {
+            Some(file_name) => {
+                let path = Path::new("uploads").join(file_name);
+                let mut file = fs::File::create(&path).await?;
+                let mut size = 0;
+                while let Some(chunk) = field.chunk().await? {
+                    size += chunk.len();
+                    file.write_all(&chunk).await?;
+                }
+
+                summary +=
+                    &format!("{name}: {size} bytes to {}\n", path.display());
+            }
+            None => {
+                let value = field.text(8 * 1024).await?;
+                summary += &format!("{name}: {value}\n");
+            }
+        }
     }
+
+    Ok(summary)
 }
@@ -0,0 +1,402 @@
+use std::io;
+
+use futures::StreamExt;
+
+use crate::{
+    body::Body,
+    http::{invalid, Headers, Request},
+};
+
+/// The most bytes of headers we will buffer for a single part.
+const MAX_PART_HEADERS: usize = 8 * 1024;
+
+/// An incremental `multipart/form-data` parser. Fields come out one at a time,
+/// and each field's contents come out a chunk at a time, so a file part is
+/// never held in memory all at once: only the current chunk plus enough bytes
+/// to spot a boundary that straddles two chunks.
+pub struct Multipart<'a> {
+    body: Body<'a>,
+
+    /// `\r\n--` followed by the boundary, which is what actually separates
+    /// parts. The CRLF before it belongs to the delimiter, not the content.
+    delimiter: Vec<u8>,
+
+    buffer: Vec<u8>,
+    state: State,
+}
+
+enum State {
+    /// Before the first boundary, where anything goes and is ignored.
+    Preamble,
+
+    /// Just past a boundary: either `--` (the end) or a new part follows.
+    Boundary,
+
+    /// Inside a part's content.
+    Content,
+
+    Done,
+}
+
+impl<'a> Multipart<'a> {
+    /// Start parsing the request's body, using the boundary from its
+    /// `Content-Type`. Fails if the request is not `multipart/form-data`.
+    pub fn from_request(request: Request<'a>) -> io::Result<Multipart<'a>> {
+        let content_type = request
+            .headers
+            .get("Content-Type")
+            .ok_or_else(|| invalid("missing Content-Type"))?;
+
+        let boundary = boundary(content_type).ok_or_else(|| {
+            invalid(format!("not multipart/form-data: {content_type}"))
+        })?;
+
+        Ok(Multipart::new(request.body, &boundary))
+    }
+
+    pub fn new(body: Body<'a>, boundary: &str) -> Multipart<'a> {
+        let delimiter = [b"\r\n--", boundary.as_bytes()].concat();
+
+        Multipart {
+            body,
+            delimiter,
+            // Pretend the body starts with a line break, so the very first
+            // boundary looks like all the others.
+            buffer: b"\r\n".to_vec(),
+            state: State::Preamble,
+        }
+    }
+
+    /// The next field, or `None` once the closing boundary has been read. Any
+    /// part of the previous field's content which was not read gets skipped.
+    pub async fn next_field(&mut self) -> io::Result<Option<Field<'_, 'a>>> {
+        loop {
+            match self.state {
+                State::Preamble => {
+                    let at = self.find_delimiter().await?;
+                    self.buffer.drain(..at + self.delimiter.len());
+                    self.state = State::Boundary;
+                }
+
+                State::Content => while self.chunk().await?.is_some() {},
+
+                State::Boundary => {
+                    while self.buffer.len() < 2 {
+                        self.fill().await?;
+                    }
+
+                    // The closing boundary may or may not have a line break
+                    // after it, so do not wait around for one.
+                    if self.buffer.starts_with(b"--") {
+                        self.state = State::Done;
+                        continue;
+                    }
+
+                    self.take_through(b"\r\n", 1024).await?;
+                    let headers = self.read_headers().await?;
+                    self.state = State::Content;
+                    return Ok(Some(Field::new(self, headers)));
+                }
+
+                State::Done => return Ok(None),
+            }
+        }
+    }
+
+    /// The next chunk of the current field's content, or `None` at its end.
+    async fn chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if !matches!(self.state, State::Content) {
+            return Ok(None);
+        }
+
+        loop {
+            if let Some(at) = find(&self.buffer, &self.delimiter) {
+                if at == 0 {
+                    self.buffer.drain(..self.delimiter.len());
+                    self.state = State::Boundary;
+                    return Ok(None);
+                }
+
+                return Ok(Some(self.buffer.drain(..at).collect()));
+            }
+
+            // Anything except the tail, which might be the start of a
+            // delimiter whose end has not arrived yet, is safe to hand out.
+            let safe =
+                self.buffer.len().saturating_sub(self.delimiter.len() - 1);
+            if safe > 0 {
+                return Ok(Some(self.buffer.drain(..safe).collect()));
+            }
+
+            self.fill().await?;
+        }
+    }
+
+    /// Wait for the delimiter to show up in the buffer, throwing away anything
+    /// in front of it which cannot be part of it.
+    async fn find_delimiter(&mut self) -> io::Result<usize> {
+        loop {
+            if let Some(at) = find(&self.buffer, &self.delimiter) {
+                return Ok(at);
+            }
+
+            let keep = self.delimiter.len() - 1;
+            let skip = self.buffer.len().saturating_sub(keep);
+            self.buffer.drain(..skip);
+            self.fill().await?;
+        }
+    }
+
+    /// Remove and return everything up to `terminator`, dropping the
+    /// terminator itself. Fails rather than buffer more than `limit` bytes.
+    async fn take_through(
+        &mut self,
+        terminator: &[u8],
+        limit: usize,
+    ) -> io::Result<Vec<u8>> {
+        loop {
+            if let Some(at) = find(&self.buffer, terminator) {
+                let taken = self.buffer.drain(..at).collect();
+                self.buffer.drain(..terminator.len());
+                return Ok(taken);
+            }
+
+            if self.buffer.len() > limit {
+                return Err(invalid("multipart line too long"));
+            }
+
+            self.fill().await?;
+        }
+    }
+
+    async fn read_headers(&mut self) -> io::Result<Headers> {
+        let mut headers = Headers::new();
+        let mut total = 0;
+
+        loop {
+            let limit = MAX_PART_HEADERS.saturating_sub(total);
+            let line = self.take_through(b"\r\n", limit).await?;
+            if line.is_empty() {
+                return Ok(headers);
+            }
+
+            total += line.len();
+            if total > MAX_PART_HEADERS {
+                return Err(invalid("multipart headers too long"));
+            }
+
+            let line = String::from_utf8(line)
+                .map_err(|_| invalid("multipart headers are not UTF-8"))?;
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| invalid(format!("bad part header: {line}")))?;
+
+            headers.append(name.trim(), value.trim());
+        }
+    }
+
+    async fn fill(&mut self) -> io::Result<()> {
+        match self.body.next().await {
+            Some(chunk) => {
+                self.buffer.extend_from_slice(&chunk?);
+                Ok(())
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "multipart body ended before its closing boundary",
+            )),
+        }
+    }
+}
+
+/// One field of a multipart body: a plain form value, or a file.
+pub struct Field<'m, 'a> {
+    multipart: &'m mut Multipart<'a>,
+    pub headers: Headers,
+    name: Option<String>,
+    file_name: Option<String>,
+}
+
+impl<'m, 'a> Field<'m, 'a> {
+    fn new(multipart: &'m mut Multipart<'a>, headers: Headers) -> Self {
+        let disposition = headers.get("Content-Disposition").unwrap_or("");
+        let name = parameter(disposition, "name");
+        let file_name = parameter(disposition, "filename");
+
+        Field {
+            multipart,
+            headers,
+            name,
+            file_name,
+        }
+    }
+
+    /// The form field's name, from `Content-Disposition`.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// The uploaded file's name, if this field is a file. This comes straight
+    /// from the client, so do not use it as a path without cleaning it up!
+    pub fn file_name(&self) -> Option<&str> {
+        self.file_name.as_deref()
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        self.headers.get("Content-Type")
+    }
+
+    /// The next chunk of this field's content, or `None` at its end.
+    pub async fn chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        self.multipart.chunk().await
+    }
+
+    /// Read the rest of the field as text, failing if it turns out to be
+    /// longer than `limit` bytes. Meant for ordinary form values, not files.
+    pub async fn text(mut self, limit: usize) -> io::Result<String> {
+        let mut bytes = Vec::new();
+        while let Some(chunk) = self.chunk().await? {
+            if bytes.len() + chunk.len() > limit {
+                return Err(invalid(format!(
+                    "field longer than {limit} bytes"
+                )));
+            }
+
+            bytes.extend_from_slice(&chunk);
+        }
+
+        String::from_utf8(bytes).map_err(|error| invalid(error.to_string()))
+    }
+}
+
+/// Pull the boundary out of a `multipart/form-data` content type.
+fn boundary(content_type: &str) -> Option<String> {
+    let (mime, _) = content_type.split_once(';')?;
+    if !mime.trim().eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+
+    parameter(content_type, "boundary")
+}
+
+/// Find a `name=value` or `name="value"` parameter in a header value like
+/// `form-data; name="upload"; filename="cat.png"`.
+fn parameter(value: &str, name: &str) -> Option<String> {
+    value.split(';').skip(1).find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        if !key.trim().eq_ignore_ascii_case(name) {
+            return None;
+        }
+
+        let value = value.trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+            .unwrap_or(value);
+
+        Some(value.to_string())
+    })
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Send `pieces` as the chunks of a chunked body, so the parser sees them
+    /// arrive one at a time, split exactly where the test wants.
+    fn chunked(pieces: &[&[u8]]) -> Vec<u8> {
+        let mut wire = Vec::new();
+        for piece in pieces {
+            wire.extend_from_slice(format!("{:x}\r\n", piece.len()).as_bytes());
+            wire.extend_from_slice(piece);
+            wire.extend_from_slice(b"\r\n");
+        }
+        wire.extend_from_slice(b"0\r\n\r\n");
+        wire
+    }
+
+    /// Every field's name and contents.
+    async fn fields(
+        pieces: &[&[u8]],
+    ) -> io::Result<Vec<(Option<String>, Vec<u8>)>> {
+        let wire = chunked(pieces);
+        let mut wire = wire.as_slice();
+        let mut headers = Headers::new();
+        headers.append("Transfer-Encoding", "chunked");
+        let mut multipart =
+            Multipart::new(Body::new(&mut wire, &headers)?, "XyZ");
+
+        let mut fields = Vec::new();
+        while let Some(mut field) = multipart.next_field().await? {
+            let name = field.name().map(String::from);
+            let mut contents = Vec::new();
+            while let Some(chunk) = field.chunk().await? {
+                contents.extend_from_slice(&chunk);
+            }
+            fields.push((name, contents));
+        }
+
+        Ok(fields)
+    }
+
+    fn field(name: &str, contents: &[u8]) -> (Option<String>, Vec<u8>) {
+        (Some(name.to_string()), contents.to_vec())
+    }
+
+    #[tokio::test]
+    async fn boundary_split_across_chunks() {
+        let fields = fields(&[
+            b"--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n",
+            b"first\r\n--X",
+            b"yZ\r\nContent-Disposition: form-data; name=\"b\"\r\n\r\nsecond\r",
+            b"\n--XyZ--\r\n",
+        ])
+        .await
+        .unwrap();
+
+        assert_eq!(fields, [field("a", b"first"), field("b", b"second")]);
+    }
+
+    #[tokio::test]
+    async fn almost_a_boundary_is_content() {
+        let fields = fields(&[
+            b"preamble\r\n--XyZ\r\n",
+            b"Content-Disposition: form-data; name=\"a\"\r\n\r\n",
+            b"one\r\n--Xy\r\n--two\r\n--XyZ--",
+        ])
+        .await
+        .unwrap();
+
+        assert_eq!(fields, [field("a", b"one\r\n--Xy\r\n--two")]);
+    }
+
+    #[tokio::test]
+    async fn closing_boundary_without_line_break() {
+        let fields = fields(&[
+            b"--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n",
+            b"last\r\n--XyZ--",
+        ])
+        .await
+        .unwrap();
+
+        assert_eq!(fields, [field("a", b"last")]);
+    }
+
+    #[tokio::test]
+    async fn oversized_part_headers_are_refused() {
+        let padding = format!("X-Padding: {}\r\n", "a".repeat(1000));
+        let head = format!(
+            "--XyZ\r\n{}\r\nbody\r\n--XyZ--\r\n",
+            padding.repeat(MAX_PART_HEADERS / 1000 + 1)
+        );
+
+        let error = fields(&[head.as_bytes()]).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
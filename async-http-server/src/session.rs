@@ -0,0 +1,469 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+use futures::{future::BoxFuture, FutureExt};
+use ring::{
+    hmac,
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::{
+    cookie::{self, SameSite, SetCookie},
+    http::{Request, Response},
+    site::Site,
+};
+
+const DEFAULT_COOKIE_NAME: &str = "session";
+
+/// How long a session lasts without being used.
+const DEFAULT_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How often `MemoryStore` sweeps out expired sessions.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// What a `SessionStore` keeps for each session.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionData {
+    pub values: HashMap<String, String>,
+    pub expires: SystemTime,
+}
+
+/// Somewhere to keep sessions between requests. `Sessions` takes care of
+/// expiry and signing, so a store only has to remember what it is given.
+pub trait SessionStore: Send + Sync {
+    fn load<'a>(
+        &'a self,
+        id: &'a str,
+    ) -> BoxFuture<'a, io::Result<Option<SessionData>>>;
+
+    fn save<'a>(
+        &'a self,
+        id: &'a str,
+        data: &'a SessionData,
+    ) -> BoxFuture<'a, io::Result<()>>;
+
+    fn remove<'a>(&'a self, id: &'a str) -> BoxFuture<'a, io::Result<()>>;
+}
+
+/// Sessions in a `HashMap`, gone when the server stops.
+#[derive(Default)]
+pub struct MemoryStore {
+    sessions: Mutex<HashMap<String, SessionData>>,
+    last_pruned: Mutex<Option<Instant>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+
+    /// Every so often, drop the sessions nobody came back for, so they do
+    /// not pile up forever.
+    fn prune(&self, sessions: &mut HashMap<String, SessionData>) {
+        let mut last_pruned = self.last_pruned.lock().unwrap();
+        if last_pruned.is_some_and(|last| last.elapsed() < PRUNE_INTERVAL) {
+            return;
+        }
+
+        let now = SystemTime::now();
+        sessions.retain(|_, data| data.expires > now);
+        *last_pruned = Some(Instant::now());
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load<'a>(
+        &'a self,
+        id: &'a str,
+    ) -> BoxFuture<'a, io::Result<Option<SessionData>>> {
+        let data = self.sessions.lock().unwrap().get(id).cloned();
+        async move { Ok(data) }.boxed()
+    }
+
+    fn save<'a>(
+        &'a self,
+        id: &'a str,
+        data: &'a SessionData,
+    ) -> BoxFuture<'a, io::Result<()>> {
+        let mut sessions = self.sessions.lock().unwrap();
+        self.prune(&mut sessions);
+        sessions.insert(id.to_string(), data.clone());
+        async { Ok(()) }.boxed()
+    }
+
+    fn remove<'a>(&'a self, id: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        self.sessions.lock().unwrap().remove(id);
+        async { Ok(()) }.boxed()
+    }
+}
+
+/// Sessions as JSON files in a directory, one per session, so they survive
+/// a restart. Nothing cleans up expired files except loading them.
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+impl FileStore {
+    pub fn new(dir: impl Into<PathBuf>) -> FileStore {
+        FileStore { dir: dir.into() }
+    }
+
+    /// Session ids are hex, so they are always safe to use as file names.
+    fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{id}.json"))
+    }
+}
+
+impl SessionStore for FileStore {
+    fn load<'a>(
+        &'a self,
+        id: &'a str,
+    ) -> BoxFuture<'a, io::Result<Option<SessionData>>> {
+        async move {
+            let bytes = match fs::read(self.path(id)).await {
+                Ok(bytes) => bytes,
+                Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                    return Ok(None);
+                }
+                Err(error) => return Err(error),
+            };
+
+            let data = serde_json::from_slice(&bytes)?;
+            Ok(Some(data))
+        }
+        .boxed()
+    }
+
+    fn save<'a>(
+        &'a self,
+        id: &'a str,
+        data: &'a SessionData,
+    ) -> BoxFuture<'a, io::Result<()>> {
+        async move {
+            fs::create_dir_all(&self.dir).await?;
+
+            // Write somewhere else first and then move it into place, so a
+            // concurrent load never sees half a file.
+            let path = self.path(id);
+            let partial = path.with_extension("json.partial");
+            fs::write(&partial, serde_json::to_vec(data)?).await?;
+            fs::rename(&partial, &path).await
+        }
+        .boxed()
+    }
+
+    fn remove<'a>(&'a self, id: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        async move {
+            match fs::remove_file(self.path(id)).await {
+                Err(error) if error.kind() != io::ErrorKind::NotFound => {
+                    Err(error)
+                }
+                _ => Ok(()),
+            }
+        }
+        .boxed()
+    }
+}
+
+/// One client's session, as a handler sees it. Changes are saved, and the
+/// cookie sent, once the handler has produced its response.
+///
+/// This is a cheap handle: clones all refer to the same session.
+#[derive(Clone)]
+pub struct Session {
+    state: Arc<Mutex<SessionState>>,
+}
+
+struct SessionState {
+    /// `None` until a brand-new session has something worth saving.
+    id: Option<String>,
+    values: HashMap<String, String>,
+    destroyed: bool,
+}
+
+impl Session {
+    fn new(id: Option<String>, values: HashMap<String, String>) -> Session {
+        Session {
+            state: Arc::new(Mutex::new(SessionState {
+                id,
+                values,
+                destroyed: false,
+            })),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.state.lock().unwrap().values.get(key).cloned()
+    }
+
+    pub fn insert(&self, key: impl Into<String>, value: impl Into<String>) {
+        let mut state = self.state.lock().unwrap();
+        state.values.insert(key.into(), value.into());
+        state.destroyed = false;
+    }
+
+    pub fn remove(&self, key: &str) -> Option<String> {
+        self.state.lock().unwrap().values.remove(key)
+    }
+
+    /// Whether this session started with this request.
+    pub fn is_new(&self) -> bool {
+        self.state.lock().unwrap().id.is_none()
+    }
+
+    /// Throw the session away, in the store and in the browser, as when
+    /// logging out.
+    pub fn destroy(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.values.clear();
+        state.destroyed = true;
+    }
+}
+
+/// Session middleware. Wrap a handler with it and the handler gets a
+/// `Session` for the client, found by a signed id in a cookie.
+///
+/// Ids are random, and the cookie carries an HMAC of the id alongside it, so a
+/// client can neither guess someone else's id nor make one up. Sessions expire
+/// once they go unused for `max_age`.
+#[derive(Clone)]
+pub struct Sessions {
+    store: Arc<dyn SessionStore>,
+    key: hmac::Key,
+    random: SystemRandom,
+    cookie_name: String,
+    max_age: Duration,
+    secure: bool,
+}
+
+impl Sessions {
+    /// Keep sessions in `store`, signing their ids with `secret`. Anyone with
+    /// the secret can forge session cookies, so keep it out of source control.
+    pub fn new(store: impl SessionStore + 'static, secret: &[u8]) -> Sessions {
+        Sessions {
+            store: Arc::new(store),
+            key: hmac::Key::new(hmac::HMAC_SHA256, secret),
+            random: SystemRandom::new(),
+            cookie_name: DEFAULT_COOKIE_NAME.to_string(),
+            max_age: DEFAULT_MAX_AGE,
+            secure: false,
+        }
+    }
+
+    /// # Panics
+    ///
+    /// Panics if `name` is not a valid cookie name.
+    pub fn cookie_name(mut self, name: impl Into<String>) -> Sessions {
+        let name = name.into();
+        assert!(cookie::is_token(&name), "invalid cookie name {name:?}");
+        self.cookie_name = name;
+        self
+    }
+
+    pub fn max_age(mut self, max_age: Duration) -> Sessions {
+        self.max_age = max_age;
+        self
+    }
+
+    /// Mark the cookie `Secure`, for sites served only over HTTPS.
+    pub fn secure(mut self, secure: bool) -> Sessions {
+        self.secure = secure;
+        self
+    }
+
+    /// Turn a handler which wants a `Session` into an ordinary one, to pass
+    /// to `Site::route`.
+    pub fn wrap<H>(
+        &self,
+        handler: H,
+    ) -> impl for<'r> Fn(&'r Site, Request<'r>) -> BoxFuture<'r, Response>
+           + Send
+           + Sync
+           + 'static
+    where
+        H: for<'r> Fn(
+                &'r Site,
+                Request<'r>,
+                Session,
+            ) -> BoxFuture<'r, Response>
+            + Send
+            + Sync
+            + 'static,
+    {
+        let sessions = self.clone();
+        let handler = Arc::new(handler);
+
+        move |site, request| {
+            let sessions = sessions.clone();
+            let handler = Arc::clone(&handler);
+
+            // Pull out the cookie now: the request cannot be borrowed across
+            // an await and still have the future be `Send`.
+            let cookie = request.cookie(&sessions.cookie_name);
+
+            async move {
+                let session = sessions.load(cookie.as_deref()).await;
+                let response = handler(site, request, session.clone()).await;
+                sessions.finish(&session, response).await
+            }
+            .boxed()
+        }
+    }
+
+    /// The session the cookie refers to, or a fresh one if there is no
+    /// cookie, it has been tampered with, or its session has expired.
+    async fn load(&self, cookie: Option<&str>) -> Session {
+        let Some(id) = cookie.and_then(|cookie| self.verify(cookie)) else {
+            return Session::new(None, HashMap::new());
+        };
+
+        match self.store.load(id).await {
+            Ok(Some(data)) if data.expires > SystemTime::now() => {
+                Session::new(Some(id.to_string()), data.values)
+            }
+            Ok(Some(_expired)) => {
+                if let Err(error) = self.store.remove(id).await {
+                    eprintln!("Could not remove expired session: {error}");
+                }
+                Session::new(None, HashMap::new())
+            }
+            Ok(None) => Session::new(None, HashMap::new()),
+            Err(error) => {
+                eprintln!("Could not load session: {error}");
+                Session::new(None, HashMap::new())
+            }
+        }
+    }
+
+    /// Save the session, pushing its expiry back, and attach the cookie.
+    async fn finish(&self, session: &Session, response: Response) -> Response {
+        let (id, data, destroyed) = {
+            let state = session.state.lock().unwrap();
+            let data = SessionData {
+                values: state.values.clone(),
+                expires: SystemTime::now() + self.max_age,
+            };
+            (state.id.clone(), data, state.destroyed)
+        };
+
+        if destroyed {
+            if let Some(id) = id {
+                if let Err(error) = self.store.remove(&id).await {
+                    eprintln!("Could not remove session: {error}");
+                }
+            }
+
+            let removal = SetCookie::removal(&self.cookie_name).path("/");
+            return response.with_cookie(&removal);
+        }
+
+        // Nobody needs a session with nothing in it.
+        if id.is_none() && data.values.is_empty() {
+            return response;
+        }
+
+        let id = id.unwrap_or_else(|| self.new_id());
+        if let Err(error) = self.store.save(&id, &data).await {
+            eprintln!("Could not save session: {error}");
+            return response;
+        }
+
+        let mut cookie = SetCookie::new(&self.cookie_name, self.sign(&id))
+            .path("/")
+            .max_age(self.max_age)
+            .http_only()
+            .same_site(SameSite::Lax);
+        if self.secure {
+            cookie = cookie.secure();
+        }
+
+        response.with_cookie(&cookie)
+    }
+
+    fn new_id(&self) -> String {
+        let mut bytes = [0; 16];
+        self.random
+            .fill(&mut bytes)
+            .expect("the system random number generator failed");
+        hex(&bytes)
+    }
+
+    /// `id.signature`, with the signature in hex.
+    fn sign(&self, id: &str) -> String {
+        let tag = hmac::sign(&self.key, id.as_bytes());
+        format!("{id}.{}", hex(tag.as_ref()))
+    }
+
+    /// The id from a signed cookie value, if the signature checks out.
+    fn verify<'c>(&self, cookie: &'c str) -> Option<&'c str> {
+        let (id, signature) = cookie.split_once('.')?;
+        let signature = unhex(signature)?;
+        hmac::verify(&self.key, id.as_bytes(), &signature).ok()?;
+        Some(id)
+    }
+}
+
+/// A random secret for `Sessions::new`. Sessions signed with it stop working
+/// when the process exits, since the secret is gone.
+pub fn random_secret() -> [u8; 32] {
+    let mut secret = [0; 32];
+    SystemRandom::new()
+        .fill(&mut secret)
+        .expect("the system random number generator failed");
+    secret
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..text.len())
+        .step_by(2)
+        .map(|at| u8::from_str_radix(text.get(at..at + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tampered_cookies_do_not_verify() {
+        let sessions = Sessions::new(MemoryStore::new(), b"secret");
+        let signed = sessions.sign("0123abcd");
+        assert_eq!(sessions.verify(&signed), Some("0123abcd"));
+
+        let (id, signature) = signed.split_once('.').unwrap();
+        let flipped = match signature.as_bytes()[0] {
+            b'0' => format!("1{}", &signature[1..]),
+            _ => format!("0{}", &signature[1..]),
+        };
+
+        let tampered = [
+            format!("0123abce.{signature}"),
+            format!("{id}.{flipped}"),
+            format!("{id}.{}", &signature[2..]),
+            format!("{id}.not-hex"),
+            format!("{id}."),
+            id.to_string(),
+        ];
+        for cookie in &tampered {
+            assert_eq!(sessions.verify(cookie), None, "{cookie}");
+        }
+
+        let other = Sessions::new(MemoryStore::new(), b"other secret");
+        assert_eq!(other.verify(&signed), None);
+    }
+}
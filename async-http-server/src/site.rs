@@ -0,0 +1,515 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use futures::future::BoxFuture;
+use serde::Deserialize;
+use tokio::fs;
+
+use crate::{
+    auth::{AuthConfig, Protection},
+    blocking::BlockingPool,
+    http::{percent_decode, Request, Response},
+    listing,
+};
+
+/// How a `Site` is set up in the config file. Routes are code, not config, so
+/// they get added to the `Site` afterward.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SiteConfig {
+    /// The `Host` names this site answers to. Ignored for the default site,
+    /// which answers to everything the other sites do not.
+    pub hosts: Vec<String>,
+
+    /// Where the site's files live. Everything under it is served, so it
+    /// should hold nothing but the site.
+    pub root: PathBuf,
+
+    /// The page to show for anything that does not exist, relative to `root`.
+    pub not_found: PathBuf,
+
+    /// Whether to list the contents of directories without an `index.html`.
+    pub listings: bool,
+
+    /// Path prefixes which need a password or token.
+    pub auth: Vec<AuthConfig>,
+}
+
+impl Default for SiteConfig {
+    fn default() -> SiteConfig {
+        SiteConfig {
+            hosts: Vec::new(),
+            root: PathBuf::from("public"),
+            not_found: PathBuf::from("404.html"),
+            listings: false,
+            auth: Vec::new(),
+        }
+    }
+}
+
+/// A request handler. It gets the `Site` the request was routed to, so it can
+/// get at files in that site's root.
+pub type Handler = Box<
+    dyn for<'r> Fn(&'r Site, Request<'r>) -> BoxFuture<'r, Response>
+        + Send
+        + Sync,
+>;
+
+struct Route {
+    method: String,
+    path: String,
+    handler: Handler,
+}
+
+/// One website: a document root to serve files from, a 404 page, and any
+/// routes which need code rather than a file.
+pub struct Site {
+    root: PathBuf,
+    not_found: PathBuf,
+    listings: bool,
+    routes: Vec<Route>,
+
+    /// Most specific prefix first.
+    protections: Vec<Protection>,
+}
+
+impl Site {
+    pub fn new(root: impl Into<PathBuf>) -> Site {
+        Site {
+            root: root.into(),
+            not_found: PathBuf::from("404.html"),
+            listings: false,
+            routes: Vec::new(),
+            protections: Vec::new(),
+        }
+    }
+
+    /// Fails if an `htpasswd` file cannot be loaded. Checking passwords
+    /// happens on `blocking`, since it is meant to be slow.
+    pub fn from_config(
+        config: &SiteConfig,
+        blocking: &BlockingPool,
+    ) -> Result<Site, String> {
+        let mut site = Site::new(&config.root)
+            .not_found(&config.not_found)
+            .listings(config.listings);
+
+        for auth in &config.auth {
+            site =
+                site.protect(Protection::from_config(auth, blocking.clone())?);
+        }
+
+        Ok(site)
+    }
+
+    /// Use a different 404 page, relative to the root.
+    pub fn not_found(mut self, page: impl Into<PathBuf>) -> Site {
+        self.not_found = page.into();
+        self
+    }
+
+    /// Turn directory listings on or off.
+    pub fn listings(mut self, listings: bool) -> Site {
+        self.listings = listings;
+        self
+    }
+
+    /// Require credentials for everything under the protection's prefix. If
+    /// prefixes overlap, the longest one that matches is the one which counts.
+    pub fn protect(mut self, protection: Protection) -> Site {
+        self.protections.push(protection);
+        self.protections.sort_by_key(|protection| {
+            std::cmp::Reverse(protection.specificity())
+        });
+        self
+    }
+
+    /// Handle `method` requests for exactly `path` with `handler` instead of
+    /// looking for a file.
+    pub fn route<H>(mut self, method: &str, path: &str, handler: H) -> Site
+    where
+        H: for<'r> Fn(&'r Site, Request<'r>) -> BoxFuture<'r, Response>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.routes.push(Route {
+            method: method.to_string(),
+            path: path.to_string(),
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Answer the request with a matching route or a file from the root,
+    /// once any credentials the path needs have been checked.
+    /// `HEAD` gets whatever `GET` would, `OPTIONS` gets the `Allow`ed methods,
+    /// and a path which exists but not for this method gets a 405.
+    pub async fn respond(&self, request: Request<'_>) -> Response {
+        // Protections, routes and files all go by the same normalised path,
+        // so no spelling of it can get past one and still reach another.
+        let Some(path) = normalize(request.path()) else {
+            return self.not_found_page().await;
+        };
+
+        let protection = self
+            .protections
+            .iter()
+            .find(|protection| protection.covers(&path));
+        if let Some(protection) = protection {
+            let authorization =
+                request.headers.get("Authorization").map(str::to_string);
+            if let Err(response) =
+                protection.check(authorization.as_deref()).await
+            {
+                return response;
+            }
+        }
+
+        let method = match request.method.as_str() {
+            "HEAD" => "GET",
+            method => method,
+        }
+        .to_string();
+
+        let route = self
+            .routes
+            .iter()
+            .find(|route| route.method == method && route.path == path);
+
+        if let Some(route) = route {
+            return (route.handler)(self, request).await;
+        }
+
+        if method == "GET" {
+            let accept = request.headers.get("Accept");
+            if let Some(response) = self.static_file(&path, accept).await {
+                return response;
+            }
+        }
+
+        let allowed = self.allowed_methods(&path).await;
+        if method == "OPTIONS" && !allowed.is_empty() {
+            return Response::new(204).with_header("Allow", allowed.join(", "));
+        }
+
+        if allowed.is_empty() {
+            return self.not_found_page().await;
+        }
+
+        Response::new(405)
+            .with_header("Allow", allowed.join(", "))
+            .with_body("Method Not Allowed\n")
+    }
+
+    /// Every method something would answer for `path`, for `Allow` headers.
+    /// Empty if nothing lives at `path` at all. The path `*` means the server
+    /// as a whole, so it gets every method any route uses.
+    async fn allowed_methods(&self, path: &str) -> Vec<&str> {
+        let mut methods = Vec::new();
+        for route in &self.routes {
+            let applies = path == "*" || route.path == path;
+            if applies && !methods.contains(&route.method.as_str()) {
+                methods.push(route.method.as_str());
+            }
+        }
+
+        let file = self.resolve(path);
+        let has_file = self.find_file(&file).await.is_some()
+            || (self.listings && is_dir(&file).await);
+
+        if (path == "*" || has_file) && !methods.contains(&"GET") {
+            methods.push("GET");
+        }
+
+        if methods.is_empty() {
+            return methods;
+        }
+
+        if methods.contains(&"GET") {
+            methods.push("HEAD");
+        }
+
+        if !methods.contains(&"OPTIONS") {
+            methods.push("OPTIONS");
+        }
+
+        methods
+    }
+
+    async fn static_file(
+        &self,
+        request_path: &str,
+        accept: Option<&str>,
+    ) -> Option<Response> {
+        let path = self.resolve(request_path);
+        if let Some(response) = self.file(&path).await {
+            return Some(response);
+        }
+
+        if !(self.listings && is_dir(&path).await) {
+            return None;
+        }
+
+        let json = listing::wants_json(accept);
+        match listing::render(&path, request_path, json).await {
+            Ok(response) => Some(response),
+            Err(error) => {
+                eprintln!("Could not list {}: {error}", path.display());
+                None
+            }
+        }
+    }
+
+    /// Serve the file at `path` (relative to the root) with the given status.
+    /// Falls back to the 404 page if it is missing.
+    pub async fn page(&self, status: u16, path: impl AsRef<Path>) -> Response {
+        match self.file(&self.root.join(path)).await {
+            Some(response) => Response { status, ..response },
+            None => self.not_found_page().await,
+        }
+    }
+
+    pub async fn not_found_page(&self) -> Response {
+        match self.file(&self.root.join(&self.not_found)).await {
+            Some(response) => Response {
+                status: 404,
+                ..response
+            },
+            None => Response::new(404).with_body("Not Found\n"),
+        }
+    }
+
+    /// A 200 response with the contents of the file at `path`, or `None` if
+    /// there is no such file. Directories get their `index.html`.
+    async fn file(&self, path: &Path) -> Option<Response> {
+        let path = self.find_file(path).await?;
+        let contents = fs::read(&path).await.ok()?;
+        Some(
+            Response::new(200)
+                .with_header("Content-Type", content_type(&path))
+                .with_body(contents),
+        )
+    }
+
+    /// The file to serve for `path`: the path itself, or its `index.html` if
+    /// it is a directory.
+    async fn find_file(&self, path: &Path) -> Option<PathBuf> {
+        let metadata = fs::metadata(path).await.ok()?;
+        if !metadata.is_dir() {
+            return Some(path.to_owned());
+        }
+
+        let index = path.join("index.html");
+        fs::metadata(&index).await.ok()?;
+        Some(index)
+    }
+
+    /// Map a path from `normalize` onto the filesystem under the root.
+    fn resolve(&self, path: &str) -> PathBuf {
+        let mut resolved = self.root.clone();
+        resolved.extend(path.split('/').filter(|segment| !segment.is_empty()));
+        resolved
+    }
+}
+
+/// Decode a request path and tidy it up: `/a//./%62/` becomes `/a/b`. Refuses
+/// anything which would climb out of the root, or which decodes to a segment
+/// no file name could have. The path `*` is left as it is.
+pub(crate) fn normalize(request_path: &str) -> Option<String> {
+    if request_path == "*" {
+        return Some(String::from("*"));
+    }
+
+    let mut path = String::new();
+    for segment in request_path.split('/') {
+        let segment = percent_decode(segment)?;
+        match segment.as_str() {
+            "" | "." => {}
+            ".." => return None,
+            segment if segment.contains(['/', '\\', '\0']) => return None,
+            segment => {
+                path.push('/');
+                path += segment;
+            }
+        }
+    }
+
+    if path.is_empty() {
+        path.push('/');
+    }
+
+    Some(path)
+}
+
+async fn is_dir(path: &Path) -> bool {
+    fs::metadata(path)
+        .await
+        .is_ok_and(|metadata| metadata.is_dir())
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("txt" | "md") => "text/plain; charset=utf-8",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Every `Site` the server knows about, keyed by the `Host` names they answer
+/// to, plus the one for everybody else.
+pub struct Sites {
+    default: Arc<Site>,
+    by_host: HashMap<String, Arc<Site>>,
+}
+
+impl Sites {
+    pub fn new(default: Site) -> Sites {
+        Sites {
+            default: Arc::new(default),
+            by_host: HashMap::new(),
+        }
+    }
+
+    pub fn add<S: AsRef<str>>(&mut self, hosts: &[S], site: Site) {
+        let site = Arc::new(site);
+        for host in hosts {
+            let host = host.as_ref().to_ascii_lowercase();
+            self.by_host.insert(host, Arc::clone(&site));
+        }
+    }
+
+    /// The site for the request's `Host`, ignoring any port.
+    pub fn site_for(&self, request: &Request<'_>) -> &Site {
+        request
+            .headers
+            .get("Host")
+            .map(|host| host_name(host).to_ascii_lowercase())
+            .and_then(|host| self.by_host.get(&host))
+            .unwrap_or(&self.default)
+    }
+
+    pub async fn respond(&self, request: Request<'_>) -> Response {
+        self.site_for(&request).respond(request).await
+    }
+}
+
+/// Drop the port from a `Host` header value, taking care not to chop up an
+/// IPv6 literal like `[::1]:7878`.
+fn host_name(host: &str) -> &str {
+    if host.starts_with('[') {
+        return host
+            .split_once(']')
+            .map_or(host, |(name, _)| &host[..=name.len()]);
+    }
+
+    host.split_once(':').map_or(host, |(name, _port)| name)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, time::Duration};
+
+    use futures::FutureExt;
+
+    use super::*;
+    use crate::{body::Body, http::Headers, listener::Peer};
+
+    fn protection(prefix: &str) -> Result<Protection, String> {
+        let config = AuthConfig {
+            prefix: prefix.to_string(),
+            realm: String::from("Admin"),
+            htpasswd: None,
+            tokens: vec![String::from("letmein")],
+        };
+        let blocking = BlockingPool::new(1, Duration::from_secs(1));
+        Protection::from_config(&config, blocking)
+    }
+
+    fn site() -> Site {
+        // Spelled oddly on purpose: it should protect `/admin` all the same.
+        let protection = protection("//%61dmin/./").unwrap();
+
+        Site::new("public").protect(protection).route(
+            "GET",
+            "/admin/secret",
+            |_site, _request| {
+                async { Response::new(200).with_body("secret\n") }.boxed()
+            },
+        )
+    }
+
+    fn get(target: &str, token: Option<&str>) -> Request<'static> {
+        let mut headers = Headers::new();
+        if let Some(token) = token {
+            headers.append("Authorization", format!("Bearer {token}"));
+        }
+
+        Request {
+            method: String::from("GET"),
+            target: target.to_string(),
+            version: String::from("HTTP/1.1"),
+            headers,
+            peer: Peer::Tcp(SocketAddr::from(([127, 0, 0, 1], 0))),
+            body: Body::empty(),
+        }
+    }
+
+    #[tokio::test]
+    async fn other_spellings_of_a_protected_path_are_protected() {
+        let site = site();
+        let targets = [
+            "/admin/secret",
+            "/%61dmin/secret",
+            "//admin/secret",
+            "/./admin/secret",
+            "/admin/./secret/",
+        ];
+        for target in targets {
+            let response = site.respond(get(target, None)).await;
+            assert_eq!(response.status, 401, "{target}");
+        }
+    }
+
+    #[tokio::test]
+    async fn other_spellings_of_a_protected_path_reach_its_route() {
+        let site = site();
+        for target in ["/%61dmin/secret", "//admin/secret", "/./admin/secret"] {
+            let response = site.respond(get(target, Some("letmein"))).await;
+            assert_eq!(response.status, 200, "{target}");
+        }
+    }
+
+    #[test]
+    fn protection_prefix_must_be_a_clean_path() {
+        assert!(protection("admin").is_err());
+        assert!(protection("/a/../admin").is_err());
+        assert!(protection("*").is_err());
+        assert!(protection("/admin/").unwrap().covers("/admin/users"));
+    }
+
+    #[test]
+    fn normalize_decodes_and_collapses() {
+        assert_eq!(normalize("/").as_deref(), Some("/"));
+        assert_eq!(normalize("//a/./%62/").as_deref(), Some("/a/b"));
+        assert_eq!(normalize("/a%20b").as_deref(), Some("/a b"));
+        assert_eq!(normalize("*").as_deref(), Some("*"));
+        assert_eq!(normalize("/a/../b"), None);
+        assert_eq!(normalize("/a/%2e%2e/b"), None);
+        assert_eq!(normalize("/a%2fb"), None);
+    }
+}
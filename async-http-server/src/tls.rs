@@ -0,0 +1,140 @@
+use std::{
+    fs::{self, OpenOptions, Permissions},
+    io::{BufReader, Write},
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use serde::Deserialize;
+use tokio_rustls::{
+    rustls::{crypto::ring, ServerConfig},
+    TlsAcceptor,
+};
+
+/// Where the HTTPS listeners bind, and where to find their certificate chain
+/// and private key, both PEM-encoded.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    /// Same format as the top-level `listen`.
+    #[serde(default = "default_listen")]
+    pub listen: Vec<String>,
+
+    pub cert: PathBuf,
+
+    pub key: PathBuf,
+
+    /// Generate a self-signed `localhost` certificate at `cert` and `key` if
+    /// they do not exist yet. Only for development: browsers will (correctly!)
+    /// complain about it.
+    #[serde(default)]
+    pub self_signed: bool,
+}
+
+fn default_listen() -> Vec<String> {
+    vec![String::from("127.0.0.1:7879")]
+}
+
+/// Build a `TlsAcceptor` from the certificate and key named in the config,
+/// generating them first if the config asks for a self-signed pair and they
+/// are not already there.
+pub fn acceptor(config: &TlsConfig) -> Result<TlsAcceptor, String> {
+    if config.self_signed && !(config.cert.exists() && config.key.exists()) {
+        generate_self_signed(&config.cert, &config.key)?;
+    }
+
+    let certs = {
+        let mut reader = BufReader::new(open(&config.cert)?);
+        rustls_pemfile::certs(&mut reader)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|error| format!("{}: {error}", config.cert.display()))?
+    };
+
+    let key = {
+        let mut reader = BufReader::new(open(&config.key)?);
+        rustls_pemfile::private_key(&mut reader)
+            .map_err(|error| format!("{}: {error}", config.key.display()))?
+            .ok_or_else(|| {
+                format!("{}: no private key found", config.key.display())
+            })?
+    };
+
+    // Name the provider explicitly rather than relying on the process-wide
+    // default, which `rustls` can only pick on its own if exactly one crypto
+    // backend is compiled in.
+    let server_config =
+        ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(|error| format!("{error}"))?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .map_err(|error| format!("{error}"))?;
+
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+/// Write a freshly generated self-signed certificate for `localhost` (and the
+/// loopback addresses) and its private key to the given paths, PEM-encoded.
+pub fn generate_self_signed(cert: &Path, key: &Path) -> Result<(), String> {
+    let names = vec![
+        String::from("localhost"),
+        String::from("127.0.0.1"),
+        String::from("::1"),
+    ];
+
+    let generated = rcgen::generate_simple_self_signed(names)
+        .map_err(|error| format!("{error}"))?;
+
+    fs::write(cert, generated.cert.pem())
+        .map_err(|error| format!("{}: {error}", cert.display()))?;
+    write_private(key, generated.key_pair.serialize_pem().as_bytes())
+        .map_err(|error| format!("{}: {error}", key.display()))?;
+
+    println!(
+        "Generated self-signed certificate at {} (key: {})",
+        cert.display(),
+        key.display()
+    );
+
+    Ok(())
+}
+
+fn open(path: &Path) -> Result<fs::File, String> {
+    fs::File::open(path).map_err(|error| format!("{}: {error}", path.display()))
+}
+
+/// Write `contents` to `path`, readable and writable by its owner alone. That
+/// goes for a file which was already there, too.
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    file.set_permissions(Permissions::from_mode(0o600))?;
+    file.write_all(contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_key_is_private() {
+        let dir = std::env::temp_dir()
+            .join(format!("async-http-server-tls-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (cert, key) = (dir.join("cert.pem"), dir.join("key.pem"));
+
+        // Even over a key which anyone could read.
+        fs::write(&key, "old").unwrap();
+        fs::set_permissions(&key, Permissions::from_mode(0o644)).unwrap();
+        generate_self_signed(&cert, &key).unwrap();
+
+        let mode = fs::metadata(&key).unwrap().permissions().mode();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
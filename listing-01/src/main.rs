@@ -1,7 +1,5 @@
-use futures::executor;
-
 fn main() {
-    executor::block_on(hello_async());
+    trpl::block_on(hello_async());
 }
 
 async fn hello_async() {
@@ -0,0 +1,66 @@
+//! Call a flaky service through a circuit breaker: watch it open after a run
+//! of failures, fail fast while open, then probe its way closed again once the
+//! service recovers. Calls go through `retry_if` too, so waiting out the
+//! cool-down is just another retry.
+//!
+//! ```text
+//! cargo run --example breaker
+//! ```
+
+use std::{
+    num::NonZeroU32,
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+
+use retry_and_timeout::{retry_if, CircuitBreaker, Fault, RetryPolicy, Schedule, TimeoutExt};
+use tokio::time;
+
+/// The service fails its first few calls, then recovers.
+static CALLS: AtomicU32 = AtomicU32::new(0);
+
+#[tokio::main]
+async fn main() {
+    let breaker = CircuitBreaker::new()
+        .failure_threshold(3)
+        .window(Duration::from_secs(1))
+        .cool_down(Duration::from_millis(200))
+        .on_state_change(|old, new| println!("  breaker: {old:?} -> {new:?}"));
+
+    let policy = RetryPolicy::new()
+        .max_attempts(NonZeroU32::new(10).unwrap())
+        .base_delay(Duration::from_millis(10))
+        .schedule(Schedule::Constant)
+        .deadline(Duration::from_secs(2));
+
+    for request in 1..=3 {
+        println!("Request {request}:");
+        let outcome = retry_if(
+            &policy,
+            || breaker.call(|| flaky().try_timeout(Duration::from_millis(50))),
+            |error| {
+                println!("  {error}");
+                error.fault(|_| Fault::Transient)
+            },
+        )
+        .await;
+
+        match outcome {
+            Ok((body, attempts)) => println!("  got '{body}' after {attempts} attempts"),
+            Err(gave_up) => println!("  {gave_up:#}"),
+        }
+    }
+}
+
+async fn flaky() -> Result<String, String> {
+    let call = CALLS.fetch_add(1, Ordering::SeqCst) + 1;
+    match call {
+        1..=2 => Err(format!("call {call}: 503 Service Unavailable")),
+        3 => {
+            // Hang, so the timeout counts it as a failure too.
+            time::sleep(Duration::from_secs(1)).await;
+            unreachable!("the timeout should have given up on call 3")
+        }
+        _ => Ok(format!("call {call}: 200 OK")),
+    }
+}
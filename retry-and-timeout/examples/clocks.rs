@@ -0,0 +1,69 @@
+//! Run `retry` without Tokio: first on a `ThreadClock` under
+//! `futures::executor`, really waiting, and then on a `VirtualClock`, walking
+//! through an hour of backoff without waiting at all.
+//!
+//! ```text
+//! cargo run --example clocks --no-default-features
+//! ```
+
+use std::{
+    cell::Cell,
+    num::NonZeroU32,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use futures::{executor::LocalPool, task::LocalSpawnExt};
+use retry_and_timeout::{retry, Clock, RetryPolicy, Schedule, ThreadClock, VirtualClock};
+
+fn main() {
+    let policy = RetryPolicy::new()
+        .max_attempts(NonZeroU32::new(4).unwrap())
+        .base_delay(Duration::from_millis(20))
+        .schedule(Schedule::Exponential)
+        .clock(ThreadClock);
+
+    let started = Instant::now();
+    let gave_up =
+        futures::executor::block_on(retry(&policy, || async { Err::<(), _>("busy") })).unwrap_err();
+    println!(
+        "ThreadClock, really waiting {:.1?}:\n{gave_up:#}\n",
+        started.elapsed()
+    );
+
+    let clock = VirtualClock::new();
+    let policy = RetryPolicy::new()
+        .max_attempts(NonZeroU32::new(20).unwrap())
+        .base_delay(Duration::from_secs(1))
+        .max_delay(Duration::from_secs(10 * 60))
+        .deadline(Duration::from_secs(60 * 60))
+        .clock(clock.clone());
+
+    let outcome = Rc::new(Cell::new(None));
+    let mut pool = LocalPool::new();
+    pool.spawner()
+        .spawn_local({
+            let outcome = Rc::clone(&outcome);
+            async move {
+                let result = retry(&policy, || async { Err::<(), _>("still busy") }).await;
+                outcome.set(Some(result));
+            }
+        })
+        .unwrap();
+
+    // Run until everything is asleep, then skip straight to the next wake-up.
+    let started = Instant::now();
+    loop {
+        pool.run_until_stalled();
+        match clock.next_wake() {
+            Some(wake) => clock.advance(wake - clock.now()),
+            None => break,
+        }
+    }
+
+    let gave_up = outcome.take().expect("retry never finished").unwrap_err();
+    println!(
+        "VirtualClock, really waiting {:.1?}:\n{gave_up:#}",
+        started.elapsed()
+    );
+}
@@ -0,0 +1,65 @@
+//! Call a service with a slow tail, first on its own and then hedged at the
+//! 90th percentile of its latency, and compare how long the slowest calls
+//! took.
+//!
+//! ```text
+//! cargo run --example hedge
+//! ```
+
+use std::{
+    convert::Infallible,
+    num::NonZeroUsize,
+    time::{Duration, Instant},
+};
+
+use rand::Rng;
+use retry_and_timeout::{hedge, HedgePolicy};
+use tokio::time;
+
+const CALLS: usize = 200;
+
+#[tokio::main]
+async fn main() {
+    let plain = HedgePolicy::fixed(Duration::ZERO).max_attempts(NonZeroUsize::MIN);
+    let hedged = HedgePolicy::percentile(0.9)
+        .initial_delay(Duration::from_millis(20))
+        .max_attempts(NonZeroUsize::new(3).unwrap());
+
+    for (label, policy) in [("plain", &plain), ("hedged at p90", &hedged)] {
+        let mut latencies = Vec::with_capacity(CALLS);
+        for _ in 0..CALLS {
+            let started = Instant::now();
+            let _ = hedge(policy, service).await;
+            latencies.push(started.elapsed());
+        }
+
+        latencies.sort_unstable();
+        let at = |p: f64| latencies[((p * CALLS as f64) as usize).min(CALLS - 1)];
+        println!(
+            "{label:>14}: p50 {:>7.1?}  p90 {:>7.1?}  p99 {:>7.1?}  max {:>7.1?}",
+            at(0.50),
+            at(0.90),
+            at(0.99),
+            latencies[CALLS - 1],
+        );
+    }
+
+    println!(
+        "The hedged calls learned to hedge after {:.1?}.",
+        hedged.delay()
+    );
+}
+
+/// Usually answers in 1–5ms, but one call in ten takes 50–100ms.
+async fn service() -> Result<(), Infallible> {
+    let millis = {
+        let mut rng = rand::thread_rng();
+        if rng.gen_bool(0.1) {
+            rng.gen_range(50..=100)
+        } else {
+            rng.gen_range(1..=5)
+        }
+    };
+    time::sleep(Duration::from_millis(millis)).await;
+    Ok(())
+}
@@ -0,0 +1,50 @@
+//! Sample a lot of backoffs for each kind of jitter and print how they spread
+//! out.
+//!
+//! ```text
+//! cargo run --example jitter
+//! ```
+
+use std::{num::NonZeroU32, time::Duration};
+
+use retry_and_timeout::{Jitter, RetryPolicy, Schedule};
+
+const RUNS: usize = 10_000;
+const RETRIES: usize = 8;
+
+fn main() {
+    let policy = RetryPolicy::new()
+        .max_attempts(NonZeroU32::new(RETRIES as u32 + 1).unwrap())
+        .base_delay(Duration::from_millis(10))
+        .max_delay(Duration::from_millis(1_000))
+        .schedule(Schedule::Exponential);
+
+    for jitter in [
+        Jitter::None,
+        Jitter::Full,
+        Jitter::Equal,
+        Jitter::Decorrelated,
+    ] {
+        let policy = policy.clone().jitter(jitter);
+        println!("{jitter:?}:");
+
+        let mut samples = vec![Vec::new(); RETRIES];
+        for run in 0..RUNS {
+            let policy = policy.clone().seed(run as u64);
+            for (retry, delay) in policy.backoff().take(RETRIES).enumerate() {
+                samples[retry].push(delay);
+            }
+        }
+
+        for (retry, delays) in samples.iter().enumerate() {
+            let min = delays.iter().min().unwrap();
+            let max = delays.iter().max().unwrap();
+            let mean = delays.iter().sum::<Duration>() / delays.len() as u32;
+            println!(
+                "  retry {}: scheduled {:>9.3?}  min {min:>9.3?}  mean {mean:>9.3?}  max {max:>9.3?}",
+                retry + 1,
+                policy.delay(retry as u32 + 1),
+            );
+        }
+    }
+}
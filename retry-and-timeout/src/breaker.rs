@@ -0,0 +1,558 @@
+use std::{
+    collections::VecDeque,
+    error::Error,
+    fmt::{self, Display},
+    future::Future,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use crate::{default_clock, Clock, Fault};
+
+/// Where a [`CircuitBreaker`] is in its cycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// Everything is let through, and failures are counted.
+    Closed,
+
+    /// Too many failures: every call fails fast until the cool-down is over.
+    Open,
+
+    /// The cool-down is over, and a few probe calls are let through to see
+    /// whether the operation works again.
+    HalfOpen,
+}
+
+/// A listener for a breaker's state changes, called with the old state and
+/// then the new one.
+type Listener = Arc<dyn Fn(State, State) + Send + Sync>;
+
+/// Stop calling an operation which keeps failing, so a struggling service gets
+/// a rest and callers get an answer right away instead of waiting on it.
+///
+/// A closed breaker counts failures over a rolling window, and opens once
+/// there are enough of them. An open breaker fails every call straight away
+/// with [`BreakerError::Open`]. After a cool-down it goes half-open and lets
+/// probe calls through: the first success closes it again, and the first
+/// failure opens it for another cool-down.
+///
+/// It composes with [`try_timeout`](crate::TimeoutExt::try_timeout) and
+/// [`retry`](crate::retry)
+/// from either side. Put the timeout inside, so a hung call counts as a
+/// failure, and the retry outside, so an open breaker's cool-down becomes the
+/// delay before the next attempt:
+///
+/// ```text
+/// retry_if(
+///     &policy,
+///     || breaker.call(|| fetch().try_timeout(Duration::from_secs(1))),
+///     |error| error.fault(|_| Fault::Transient),
+/// )
+/// ```
+///
+/// Cloning a `CircuitBreaker` gives another handle to the same circuit.
+#[derive(Clone)]
+pub struct CircuitBreaker {
+    circuit: Arc<Mutex<Circuit>>,
+    failure_threshold: usize,
+    window: Duration,
+    cool_down: Duration,
+    probes: usize,
+    listeners: Vec<Listener>,
+    clock: Arc<dyn Clock>,
+}
+
+struct Circuit {
+    state: State,
+
+    /// When each failure in the window happened, oldest first.
+    failures: VecDeque<Instant>,
+
+    /// When the breaker last opened.
+    opened: Instant,
+
+    /// Probe calls running right now, while half-open.
+    probing: usize,
+}
+
+impl CircuitBreaker {
+    /// A breaker which opens after 5 failures within 10s, and cools down for
+    /// 30s before letting a single probe through.
+    pub fn new() -> CircuitBreaker {
+        let clock = default_clock();
+        CircuitBreaker {
+            circuit: Arc::new(Mutex::new(Circuit {
+                state: State::Closed,
+                failures: VecDeque::new(),
+                opened: clock.now(),
+                probing: 0,
+            })),
+            failure_threshold: 5,
+            window: Duration::from_secs(10),
+            cool_down: Duration::from_secs(30),
+            probes: 1,
+            listeners: Vec::new(),
+            clock,
+        }
+    }
+
+    /// Open once this many failures fall within the window.
+    ///
+    /// # Panics
+    ///
+    /// If `threshold` is zero.
+    pub fn failure_threshold(mut self, threshold: usize) -> CircuitBreaker {
+        assert!(
+            threshold > 0,
+            "a circuit breaker needs a failure threshold of at least 1"
+        );
+        self.failure_threshold = threshold;
+        self
+    }
+
+    /// Only count failures from this long ago or less.
+    pub fn window(mut self, window: Duration) -> CircuitBreaker {
+        self.window = window;
+        self
+    }
+
+    /// Stay open this long before going half-open.
+    pub fn cool_down(mut self, cool_down: Duration) -> CircuitBreaker {
+        self.cool_down = cool_down;
+        self
+    }
+
+    /// Let this many probe calls run at once while half-open.
+    ///
+    /// # Panics
+    ///
+    /// If `probes` is zero.
+    pub fn probes(mut self, probes: usize) -> CircuitBreaker {
+        assert!(
+            probes > 0,
+            "a circuit breaker needs to let at least 1 probe through"
+        );
+        self.probes = probes;
+        self
+    }
+
+    /// Call `listener` with the old and new state whenever the state changes.
+    /// It runs on whichever task made the change, so it should be quick.
+    pub fn on_state_change(
+        mut self,
+        listener: impl Fn(State, State) + Send + Sync + 'static,
+    ) -> CircuitBreaker {
+        self.listeners.push(Arc::new(listener));
+        self
+    }
+
+    /// Tell the time with `clock`, instead of the default one.
+    pub fn clock(mut self, clock: impl Clock + 'static) -> CircuitBreaker {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// The state right now. An open breaker whose cool-down is over still
+    /// says it is open until the next call finds out.
+    pub fn state(&self) -> State {
+        self.lock().state
+    }
+
+    /// Run `op` if the breaker allows it, and count how it went.
+    pub async fn call<F, Fut, T, E>(&self, op: F) -> Result<T, BreakerError<E>>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let probe = self.admit()?;
+
+        // If the call is dropped before it finishes, give its probe slot back
+        // without counting it either way.
+        let mut guard = ProbeGuard {
+            breaker: self,
+            probe,
+        };
+        let result = op().await;
+        guard.probe = false;
+
+        self.record(probe, result.is_ok());
+        result.map_err(BreakerError::Inner)
+    }
+
+    /// Whether a call may go ahead, and if so, whether it is a probe.
+    fn admit<E>(&self) -> Result<bool, BreakerError<E>> {
+        let mut circuit = self.lock();
+        let mut changed = None;
+
+        if circuit.state == State::Open {
+            let cooled = self.clock.now().saturating_duration_since(circuit.opened);
+            if cooled < self.cool_down {
+                return Err(BreakerError::Open {
+                    retry_after: self.cool_down - cooled,
+                });
+            }
+
+            changed = circuit.set(State::HalfOpen);
+        }
+
+        let admitted = match circuit.state {
+            State::Closed => Ok(false),
+            State::HalfOpen if circuit.probing < self.probes => {
+                circuit.probing += 1;
+                Ok(true)
+            }
+            State::HalfOpen | State::Open => Err(BreakerError::Open {
+                retry_after: Duration::ZERO,
+            }),
+        };
+
+        drop(circuit);
+        self.notify(changed);
+        admitted
+    }
+
+    fn record(&self, probe: bool, succeeded: bool) {
+        let mut circuit = self.lock();
+        let now = self.clock.now();
+        if probe {
+            circuit.probing -= 1;
+        }
+
+        // Only probes settle a half-open breaker: a call let through while it
+        // was closed, which finishes after it has opened and cooled down, says
+        // nothing about how things stand now.
+        let changed = match (probe, circuit.state, succeeded) {
+            (true, State::HalfOpen, true) => {
+                circuit.failures.clear();
+                circuit.set(State::Closed)
+            }
+            (true, State::HalfOpen, false) => {
+                circuit.opened = now;
+                circuit.set(State::Open)
+            }
+            (false, State::Closed, false) => {
+                circuit.failures.push_back(now);
+                while circuit
+                    .failures
+                    .front()
+                    .is_some_and(|&failed| now.saturating_duration_since(failed) > self.window)
+                {
+                    circuit.failures.pop_front();
+                }
+
+                if circuit.failures.len() >= self.failure_threshold {
+                    circuit.failures.clear();
+                    circuit.opened = now;
+                    circuit.set(State::Open)
+                } else {
+                    None
+                }
+            }
+
+            // Nor does a call which started before the breaker opened, a
+            // success while closed, or a probe which finishes after another
+            // has already settled things.
+            _ => None,
+        };
+
+        drop(circuit);
+        self.notify(changed);
+    }
+
+    /// Tell the listeners, once the lock is released so they may look at the
+    /// breaker themselves.
+    fn notify(&self, changed: Option<(State, State)>) {
+        if let Some((old, new)) = changed {
+            for listener in &self.listeners {
+                listener(old, new);
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Circuit> {
+        self.circuit
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> CircuitBreaker {
+        CircuitBreaker::new()
+    }
+}
+
+impl Circuit {
+    /// Move to `state`, handing back the change if there was one.
+    fn set(&mut self, state: State) -> Option<(State, State)> {
+        let old = std::mem::replace(&mut self.state, state);
+        (old != state).then_some((old, state))
+    }
+}
+
+struct ProbeGuard<'b> {
+    breaker: &'b CircuitBreaker,
+    probe: bool,
+}
+
+impl Drop for ProbeGuard<'_> {
+    fn drop(&mut self) {
+        if self.probe {
+            self.breaker.lock().probing -= 1;
+        }
+    }
+}
+
+/// Why a call through a [`CircuitBreaker`] failed.
+#[derive(Debug)]
+pub enum BreakerError<E> {
+    /// The breaker is open, so the operation did not run. It will let a probe
+    /// through after `retry_after`, or may already be busy probing if that
+    /// is zero.
+    Open { retry_after: Duration },
+
+    /// The operation ran and failed.
+    Inner(E),
+}
+
+impl<E> BreakerError<E> {
+    /// How [`retry_if`](crate::retry_if) should treat this error: an open
+    /// breaker is worth trying again once it cools down, and `classify` says
+    /// about the operation's own errors. A breaker busy probing has no idea
+    /// when it will be free, so that is left to the policy's backoff.
+    pub fn fault(&self, classify: impl FnOnce(&E) -> Fault) -> Fault {
+        match self {
+            BreakerError::Open { retry_after } if retry_after.is_zero() => Fault::Transient,
+            BreakerError::Open { retry_after } => Fault::RetryAfter(*retry_after),
+            BreakerError::Inner(error) => classify(error),
+        }
+    }
+}
+
+impl<E: Display> Display for BreakerError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BreakerError::Open { retry_after } if retry_after.is_zero() => {
+                write!(f, "circuit breaker is half-open and busy probing")
+            }
+            BreakerError::Open { retry_after } => {
+                write!(f, "circuit breaker is open, try again in {retry_after:.1?}")
+            }
+            BreakerError::Inner(error) => error.fmt(f),
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for BreakerError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BreakerError::Open { .. } => None,
+            BreakerError::Inner(error) => Some(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::pin::pin;
+
+    use futures::{channel::oneshot, executor::block_on, poll};
+
+    use super::*;
+    use crate::VirtualClock;
+
+    const COOL_DOWN: Duration = Duration::from_secs(30);
+
+    type Changes = Arc<Mutex<Vec<(State, State)>>>;
+
+    /// A breaker on `clock` which opens after 3 failures, with every state
+    /// change it makes recorded in the returned list.
+    fn breaker(clock: &VirtualClock) -> (CircuitBreaker, Changes) {
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let breaker = CircuitBreaker::new()
+            .failure_threshold(3)
+            .window(Duration::from_secs(10))
+            .cool_down(COOL_DOWN)
+            .clock(clock.clone())
+            .on_state_change({
+                let changes = Arc::clone(&changes);
+                move |old, new| changes.lock().unwrap().push((old, new))
+            });
+        (breaker, changes)
+    }
+
+    fn fail(breaker: &CircuitBreaker) -> Result<(), BreakerError<&'static str>> {
+        block_on(breaker.call(|| async { Err("down") }))
+    }
+
+    fn succeed(breaker: &CircuitBreaker) -> Result<(), BreakerError<&'static str>> {
+        block_on(breaker.call(|| async { Ok(()) }))
+    }
+
+    #[test]
+    fn opens_cools_down_and_closes_again() {
+        let clock = VirtualClock::new();
+        let (breaker, changes) = breaker(&clock);
+
+        for _ in 0..2 {
+            assert!(matches!(fail(&breaker), Err(BreakerError::Inner("down"))));
+        }
+        assert_eq!(breaker.state(), State::Closed);
+
+        assert!(matches!(fail(&breaker), Err(BreakerError::Inner("down"))));
+        assert_eq!(breaker.state(), State::Open);
+
+        // While open, calls fail fast, with how long is left to wait.
+        clock.advance(Duration::from_secs(10));
+        let error = succeed(&breaker).unwrap_err();
+        assert!(
+            matches!(error, BreakerError::Open { retry_after } if retry_after == COOL_DOWN - Duration::from_secs(10))
+        );
+        assert_eq!(
+            error.fault(|_| Fault::Permanent),
+            Fault::RetryAfter(Duration::from_secs(20))
+        );
+
+        // Once cooled down, a successful probe closes it.
+        clock.advance(Duration::from_secs(20));
+        assert!(succeed(&breaker).is_ok());
+        assert_eq!(breaker.state(), State::Closed);
+
+        assert_eq!(
+            *changes.lock().unwrap(),
+            [
+                (State::Closed, State::Open),
+                (State::Open, State::HalfOpen),
+                (State::HalfOpen, State::Closed),
+            ]
+        );
+    }
+
+    #[test]
+    fn failed_probe_opens_it_again() {
+        let clock = VirtualClock::new();
+        let (breaker, changes) = breaker(&clock);
+        for _ in 0..3 {
+            let _ = fail(&breaker);
+        }
+
+        clock.advance(COOL_DOWN);
+        assert!(matches!(fail(&breaker), Err(BreakerError::Inner("down"))));
+        assert_eq!(breaker.state(), State::Open);
+
+        // The cool-down starts over from the failed probe.
+        clock.advance(COOL_DOWN / 2);
+        assert!(matches!(succeed(&breaker), Err(BreakerError::Open { .. })));
+
+        assert_eq!(
+            *changes.lock().unwrap(),
+            [
+                (State::Closed, State::Open),
+                (State::Open, State::HalfOpen),
+                (State::HalfOpen, State::Open),
+            ]
+        );
+    }
+
+    #[test]
+    fn failures_outside_the_window_do_not_count() {
+        let clock = VirtualClock::new();
+        let (breaker, _) = breaker(&clock);
+        for _ in 0..5 {
+            let _ = fail(&breaker);
+            clock.advance(Duration::from_secs(6));
+        }
+
+        assert_eq!(breaker.state(), State::Closed);
+    }
+
+    #[test]
+    fn late_calls_do_not_settle_a_half_open_breaker() {
+        let clock = VirtualClock::new();
+        let (breaker, changes) = breaker(&clock);
+
+        block_on(async {
+            // Two calls start while closed, and are still out while the
+            // breaker opens and cools down.
+            let (finish_ok, finished_ok) = oneshot::channel::<()>();
+            let mut late_success = pin!(breaker.call(|| async {
+                let _ = finished_ok.await;
+                Ok::<_, &str>(())
+            }));
+            assert!(poll!(late_success.as_mut()).is_pending());
+
+            let (finish_err, finished_err) = oneshot::channel::<()>();
+            let mut late_failure = pin!(breaker.call(|| async {
+                let _ = finished_err.await;
+                Err::<(), _>("down")
+            }));
+            assert!(poll!(late_failure.as_mut()).is_pending());
+
+            for _ in 0..3 {
+                let _ = breaker.call(|| async { Err::<(), _>("down") }).await;
+            }
+            clock.advance(COOL_DOWN);
+
+            let (finish_probe, finished_probe) = oneshot::channel::<()>();
+            let mut probe = pin!(breaker.call(|| async {
+                let _ = finished_probe.await;
+                Ok::<_, &str>(())
+            }));
+            assert!(poll!(probe.as_mut()).is_pending());
+            assert_eq!(breaker.state(), State::HalfOpen);
+
+            // The late calls come back before the probe does, and change
+            // nothing either way.
+            finish_ok.send(()).unwrap();
+            assert!(late_success.await.is_ok());
+            assert_eq!(breaker.state(), State::HalfOpen);
+
+            finish_err.send(()).unwrap();
+            assert!(late_failure.await.is_err());
+            assert_eq!(breaker.state(), State::HalfOpen);
+
+            finish_probe.send(()).unwrap();
+            assert!(probe.await.is_ok());
+        });
+
+        assert_eq!(
+            *changes.lock().unwrap(),
+            [
+                (State::Closed, State::Open),
+                (State::Open, State::HalfOpen),
+                (State::HalfOpen, State::Closed),
+            ]
+        );
+    }
+
+    #[test]
+    fn busy_probing_is_transient() {
+        let clock = VirtualClock::new();
+        let (breaker, _) = breaker(&clock);
+        for _ in 0..3 {
+            let _ = fail(&breaker);
+        }
+        clock.advance(COOL_DOWN);
+
+        block_on(async {
+            let (finish, finished) = oneshot::channel::<()>();
+            let mut probe = pin!(breaker.call(|| async {
+                let _ = finished.await;
+                Ok::<_, &str>(())
+            }));
+            assert!(poll!(probe.as_mut()).is_pending());
+
+            let error = breaker
+                .call(|| async { Ok::<_, &str>(()) })
+                .await
+                .unwrap_err();
+            assert!(matches!(error, BreakerError::Open { retry_after } if retry_after.is_zero()));
+            assert_eq!(error.fault(|_| Fault::Permanent), Fault::Transient);
+
+            finish.send(()).unwrap();
+            assert!(probe.await.is_ok());
+        });
+
+        assert_eq!(breaker.state(), State::Closed);
+    }
+}
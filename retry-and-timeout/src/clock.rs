@@ -0,0 +1,394 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    fmt::Debug,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Condvar, Mutex, MutexGuard, OnceLock},
+    task::{Context, Poll, Waker},
+    thread,
+    time::{Duration, Instant},
+};
+
+use futures::{
+    channel::oneshot,
+    future::{self, BoxFuture},
+    FutureExt,
+};
+
+/// Where [`retry`](crate::retry), [`timeout`](crate::timeout) and the rest get
+/// the time from, and how they wait for it to pass.
+///
+/// [`TokioClock`] is the default when the `tokio` feature is on, and
+/// [`ThreadClock`] otherwise. [`VirtualClock`] only moves when told to, for
+/// tests which should not have to wait.
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// A future which finishes once `deadline` has passed.
+    fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()>;
+
+    /// A future which finishes once `duration` has passed, counting from now.
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        match self.now().checked_add(duration) {
+            Some(deadline) => self.sleep_until(deadline),
+
+            // Too far off to ever come round.
+            None => future::pending().boxed(),
+        }
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for Arc<C> {
+    fn now(&self) -> Instant {
+        (**self).now()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()> {
+        (**self).sleep_until(deadline)
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        (**self).sleep(duration)
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for &C {
+    fn now(&self) -> Instant {
+        (**self).now()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()> {
+        (**self).sleep_until(deadline)
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        (**self).sleep(duration)
+    }
+}
+
+/// The clock everything uses unless told otherwise.
+pub fn default_clock() -> Arc<dyn Clock> {
+    #[cfg(feature = "tokio")]
+    return Arc::new(TokioClock);
+
+    #[cfg(not(feature = "tokio"))]
+    return Arc::new(ThreadClock);
+}
+
+/// The real time, slept through with Tokio's timers. Sleeping outside a Tokio
+/// runtime panics.
+#[cfg(feature = "tokio")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioClock;
+
+#[cfg(feature = "tokio")]
+impl Clock for TokioClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()> {
+        tokio::time::sleep_until(deadline.into()).boxed()
+    }
+}
+
+/// The real time, slept through with a timer thread of our own, so it works
+/// under any executor: `futures::executor`, Tokio, or anything else.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ThreadClock;
+
+impl Clock for ThreadClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()> {
+        if deadline <= Instant::now() {
+            return future::ready(()).boxed();
+        }
+
+        let (wake, woken) = oneshot::channel();
+        timer().schedule(deadline, wake);
+        woken.map(|_| ()).boxed()
+    }
+}
+
+/// The one thread every [`ThreadClock`] shares, which sleeps until the next
+/// alarm is due and rings it.
+struct Timer {
+    alarms: Mutex<Alarms>,
+    changed: Condvar,
+}
+
+struct Alarms {
+    heap: BinaryHeap<Reverse<Alarm>>,
+
+    /// How big `heap` may grow before it is next cleared of alarms nobody is
+    /// waiting for any more.
+    prune_at: usize,
+}
+
+struct Alarm {
+    at: Instant,
+    wake: oneshot::Sender<()>,
+}
+
+// Alarms only need ordering by when they go off.
+impl PartialEq for Alarm {
+    fn eq(&self, other: &Alarm) -> bool {
+        self.at == other.at
+    }
+}
+
+impl Eq for Alarm {}
+
+impl PartialOrd for Alarm {
+    fn partial_cmp(&self, other: &Alarm) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Alarm {
+    fn cmp(&self, other: &Alarm) -> std::cmp::Ordering {
+        self.at.cmp(&other.at)
+    }
+}
+
+/// The fewest alarms worth pruning.
+const MIN_PRUNE: usize = 64;
+
+fn timer() -> &'static Timer {
+    static TIMER: OnceLock<&'static Timer> = OnceLock::new();
+    TIMER.get_or_init(|| {
+        let timer: &'static Timer = Box::leak(Box::new(Timer::new()));
+
+        thread::Builder::new()
+            .name(String::from("retry-and-timeout timer"))
+            .spawn(move || timer.run())
+            .expect("could not start the timer thread");
+
+        timer
+    })
+}
+
+impl Timer {
+    fn new() -> Timer {
+        Timer {
+            alarms: Mutex::new(Alarms {
+                heap: BinaryHeap::new(),
+                prune_at: MIN_PRUNE,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    fn schedule(&self, at: Instant, wake: oneshot::Sender<()>) {
+        let mut alarms = lock(&self.alarms);
+
+        // A sleep dropped before it is due, like the loser of a timeout,
+        // leaves its alarm behind until then, which could be hours. Clear
+        // those out whenever the heap has doubled since the last time, so it
+        // stays in proportion to the sleeps still waiting.
+        if alarms.heap.len() >= alarms.prune_at {
+            alarms
+                .heap
+                .retain(|Reverse(alarm)| !alarm.wake.is_canceled());
+            alarms.prune_at = (alarms.heap.len() * 2).max(MIN_PRUNE);
+        }
+
+        alarms.heap.push(Reverse(Alarm { at, wake }));
+        drop(alarms);
+        self.changed.notify_one();
+    }
+
+    fn run(&self) {
+        let mut alarms = lock(&self.alarms);
+        loop {
+            let now = Instant::now();
+            while alarms
+                .heap
+                .peek()
+                .is_some_and(|Reverse(alarm)| alarm.at <= now)
+            {
+                let Reverse(alarm) = alarms.heap.pop().unwrap();
+
+                // Whoever was waiting may have given up already.
+                let _ = alarm.wake.send(());
+            }
+
+            alarms = match alarms.heap.peek() {
+                Some(Reverse(next)) => {
+                    let wait = next.at - now;
+                    self.changed
+                        .wait_timeout(alarms, wait)
+                        .unwrap_or_else(|poisoned| poisoned.into_inner())
+                        .0
+                }
+                None => self
+                    .changed
+                    .wait(alarms)
+                    .unwrap_or_else(|poisoned| poisoned.into_inner()),
+            };
+        }
+    }
+}
+
+/// A clock which stands still until [`advance`](VirtualClock::advance)d, so a
+/// test can walk `retry` through minutes of backoff without waiting for any
+/// of it. It starts at the real time it was made.
+///
+/// Cloning a `VirtualClock` gives another handle to the same time.
+#[derive(Debug, Clone)]
+pub struct VirtualClock {
+    state: Arc<Mutex<Virtual>>,
+}
+
+#[derive(Debug)]
+struct Virtual {
+    now: Instant,
+    next_id: u64,
+
+    /// Futures waiting for a time still to come, by id.
+    sleepers: HashMap<u64, (Instant, Option<Waker>)>,
+}
+
+impl VirtualClock {
+    pub fn new() -> VirtualClock {
+        VirtualClock {
+            state: Arc::new(Mutex::new(Virtual {
+                now: Instant::now(),
+                next_id: 0,
+                sleepers: HashMap::new(),
+            })),
+        }
+    }
+
+    /// Move time forward, waking everything which was waiting for it.
+    pub fn advance(&self, duration: Duration) {
+        let mut state = lock(&self.state);
+        state.now += duration;
+
+        let now = state.now;
+        let woken: Vec<_> = state
+            .sleepers
+            .values_mut()
+            .filter(|(deadline, _)| *deadline <= now)
+            .filter_map(|(_, waker)| waker.take())
+            .collect();
+
+        drop(state);
+        woken.into_iter().for_each(Waker::wake);
+    }
+
+    /// When the soonest sleeper wants waking, if anything is asleep. Advancing
+    /// to each of these in turn runs through a schedule as fast as possible.
+    pub fn next_wake(&self) -> Option<Instant> {
+        lock(&self.state)
+            .sleepers
+            .values()
+            .map(|(deadline, _)| *deadline)
+            .min()
+    }
+}
+
+impl Default for VirtualClock {
+    fn default() -> VirtualClock {
+        VirtualClock::new()
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Instant {
+        lock(&self.state).now
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()> {
+        let mut state = lock(&self.state);
+        let id = state.next_id;
+        state.next_id += 1;
+        state.sleepers.insert(id, (deadline, None));
+
+        VirtualSleep {
+            state: Arc::clone(&self.state),
+            id,
+        }
+        .boxed()
+    }
+}
+
+struct VirtualSleep {
+    state: Arc<Mutex<Virtual>>,
+    id: u64,
+}
+
+impl Future for VirtualSleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = lock(&self.state);
+        let now = state.now;
+        let Some((deadline, waker)) = state.sleepers.get_mut(&self.id) else {
+            return Poll::Ready(());
+        };
+
+        if *deadline <= now {
+            state.sleepers.remove(&self.id);
+            return Poll::Ready(());
+        }
+
+        *waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Drop for VirtualSleep {
+    fn drop(&mut self) {
+        lock(&self.state).sleepers.remove(&self.id);
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn abandoned_alarms_are_pruned() {
+        let timer = Timer::new();
+        let later = Instant::now() + Duration::from_secs(3600);
+
+        // A steady trickle of sleeps which are all given up on, and one which
+        // is still waiting.
+        let (wake, _waiting) = oneshot::channel();
+        timer.schedule(later, wake);
+        for _ in 0..10 * MIN_PRUNE {
+            let (wake, woken) = oneshot::channel();
+            timer.schedule(later, wake);
+            drop(woken);
+        }
+
+        let alarms = lock(&timer.alarms);
+        assert!(
+            alarms.heap.len() <= MIN_PRUNE,
+            "{} alarms",
+            alarms.heap.len()
+        );
+        assert!(alarms
+            .heap
+            .iter()
+            .any(|Reverse(alarm)| !alarm.wake.is_canceled()));
+    }
+
+    #[test]
+    fn thread_clock_sleeps() {
+        let started = Instant::now();
+        futures::executor::block_on(ThreadClock.sleep(Duration::from_millis(20)));
+        assert!(started.elapsed() >= Duration::from_millis(20));
+    }
+}
@@ -0,0 +1,289 @@
+use std::{
+    error::Error,
+    fmt::{self, Display},
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use futures::{
+    future::TryFutureExt,
+    stream::{self, Stream, StreamExt},
+};
+
+use crate::{default_clock, Clock};
+
+/// [`timeout`](crate::timeout) and [`deadline`](crate::deadline) as methods,
+/// for any future:
+///
+/// ```text
+/// let body = fetch(url).timeout(Duration::from_secs(5)).await?;
+/// ```
+///
+/// Each has an `_on` twin which is timed by a given [`Clock`] instead of the
+/// default one.
+pub trait TimeoutExt: Future + Sized {
+    /// Give up on this future after `duration`. On timeout, the error is the
+    /// duration which ran out.
+    fn timeout(self, duration: Duration) -> impl Future<Output = Result<Self::Output, Duration>> {
+        crate::timeout(duration, self)
+    }
+
+    /// Give up on this future at `deadline`. On timeout, the error is how
+    /// long it had, from when it was first polled.
+    fn deadline(self, deadline: Instant) -> impl Future<Output = Result<Self::Output, Duration>> {
+        crate::deadline(deadline, self)
+    }
+
+    /// Give up on this fallible future after `duration`, folding a timeout
+    /// and the future's own error into one [`TimeoutError`], so `?` can
+    /// handle both at once.
+    fn try_timeout<T, E>(
+        self,
+        duration: Duration,
+    ) -> impl Future<Output = Result<T, TimeoutError<E>>>
+    where
+        Self: Future<Output = Result<T, E>>,
+    {
+        crate::timeout(duration, self.map_err(TimeoutError::Inner))
+            .unwrap_or_else(|elapsed| Err(TimeoutError::Elapsed(elapsed)))
+    }
+
+    /// [`timeout`](TimeoutExt::timeout), timed by `clock`.
+    fn timeout_on<C: Clock + ?Sized>(
+        self,
+        clock: &C,
+        duration: Duration,
+    ) -> impl Future<Output = Result<Self::Output, Duration>> {
+        crate::timeout_on(clock, duration, self)
+    }
+
+    /// [`deadline`](TimeoutExt::deadline), timed by `clock`.
+    fn deadline_on<C: Clock + ?Sized>(
+        self,
+        clock: &C,
+        deadline: Instant,
+    ) -> impl Future<Output = Result<Self::Output, Duration>> {
+        crate::deadline_on(clock, deadline, self)
+    }
+
+    /// [`try_timeout`](TimeoutExt::try_timeout), timed by `clock`.
+    fn try_timeout_on<C: Clock + ?Sized, T, E>(
+        self,
+        clock: &C,
+        duration: Duration,
+    ) -> impl Future<Output = Result<T, TimeoutError<E>>>
+    where
+        Self: Future<Output = Result<T, E>>,
+    {
+        crate::timeout_on(clock, duration, self.map_err(TimeoutError::Inner))
+            .unwrap_or_else(|elapsed| Err(TimeoutError::Elapsed(elapsed)))
+    }
+}
+
+impl<F: Future> TimeoutExt for F {}
+
+/// Time limits for streams. Like [`TimeoutExt`], each has an `_on` twin timed
+/// by a given [`Clock`].
+pub trait TimeoutStreamExt: Stream + Sized {
+    /// Give up waiting on any one item after `duration`, counting from when
+    /// the stream is first polled or from the item before. A late item shows
+    /// up as an `Err` with the duration which ran out, and the stream carries
+    /// on, so the caller decides whether to keep waiting.
+    fn timeout_each(self, duration: Duration) -> impl Stream<Item = Result<Self::Item, Duration>> {
+        timeout_each(self, default_clock(), duration)
+    }
+
+    /// Give up on the whole stream `duration` after it is first polled. If
+    /// the stream has not finished by then, it ends with a single `Err` with
+    /// the duration which ran out.
+    fn timeout_total(self, duration: Duration) -> impl Stream<Item = Result<Self::Item, Duration>> {
+        timeout_total(self, default_clock(), duration)
+    }
+
+    /// [`timeout_each`](TimeoutStreamExt::timeout_each), timed by `clock`.
+    fn timeout_each_on<C: Clock + ?Sized>(
+        self,
+        clock: &C,
+        duration: Duration,
+    ) -> impl Stream<Item = Result<Self::Item, Duration>> {
+        timeout_each(self, clock, duration)
+    }
+
+    /// [`timeout_total`](TimeoutStreamExt::timeout_total), timed by `clock`.
+    fn timeout_total_on<C: Clock + ?Sized>(
+        self,
+        clock: &C,
+        duration: Duration,
+    ) -> impl Stream<Item = Result<Self::Item, Duration>> {
+        timeout_total(self, clock, duration)
+    }
+}
+
+impl<S: Stream> TimeoutStreamExt for S {}
+
+fn timeout_each<S, C>(
+    stream: S,
+    clock: C,
+    duration: Duration,
+) -> impl Stream<Item = Result<S::Item, Duration>>
+where
+    S: Stream,
+    C: Clock,
+{
+    stream::unfold(
+        (Box::pin(stream), clock),
+        move |(mut stream, clock)| async move {
+            match crate::timeout_on(&clock, duration, stream.next()).await {
+                Ok(Some(item)) => Some((Ok(item), (stream, clock))),
+                Ok(None) => None,
+                Err(elapsed) => Some((Err(elapsed), (stream, clock))),
+            }
+        },
+    )
+}
+
+fn timeout_total<S, C>(
+    stream: S,
+    clock: C,
+    duration: Duration,
+) -> impl Stream<Item = Result<S::Item, Duration>>
+where
+    S: Stream,
+    C: Clock,
+{
+    let state = (Box::pin(stream), clock, None::<Instant>, false);
+    stream::unfold(state, move |(mut stream, clock, until, done)| async move {
+        if done {
+            return None;
+        }
+
+        let until = until.unwrap_or_else(|| clock.now() + duration);
+        match crate::deadline_on(&clock, until, stream.next()).await {
+            Ok(Some(item)) => Some((Ok(item), (stream, clock, Some(until), false))),
+            Ok(None) => None,
+            Err(_) => Some((Err(duration), (stream, clock, Some(until), true))),
+        }
+    })
+}
+
+/// Why a [`try_timeout`](TimeoutExt::try_timeout) future failed.
+#[derive(Debug, PartialEq, Eq)]
+pub enum TimeoutError<E> {
+    /// It took too long, and was given up on after this long.
+    Elapsed(Duration),
+
+    /// It finished in time, but with an error.
+    Inner(E),
+}
+
+impl<E: Display> Display for TimeoutError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimeoutError::Elapsed(duration) => write!(f, "timed out after {duration:.1?}"),
+            TimeoutError::Inner(error) => error.fmt(f),
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for TimeoutError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TimeoutError::Elapsed(_) => None,
+            TimeoutError::Inner(error) => Some(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::stream;
+
+    use super::*;
+    use crate::{tests::run, VirtualClock};
+
+    /// A stream on `clock` which yields each of `gaps` as an item, after
+    /// waiting that long.
+    fn spaced(clock: &VirtualClock, gaps: &[u64]) -> impl Stream<Item = Duration> + 'static {
+        let clock = clock.clone();
+        let gaps: Vec<_> = gaps.iter().copied().map(Duration::from_secs).collect();
+        stream::iter(gaps).then(move |gap| {
+            let sleep = clock.sleep(gap);
+            async move {
+                sleep.await;
+                gap
+            }
+        })
+    }
+
+    #[test]
+    fn timeout_on_gives_up_on_the_clock() {
+        let clock = VirtualClock::new();
+        let started = clock.now();
+
+        let slow = clock.sleep(Duration::from_secs(60));
+        let outcome = run(&clock, {
+            let clock = clock.clone();
+            async move { slow.timeout_on(&clock, Duration::from_secs(5)).await }
+        });
+
+        assert_eq!(outcome, Err(Duration::from_secs(5)));
+        assert_eq!(clock.now() - started, Duration::from_secs(5));
+    }
+
+    #[test]
+    fn try_timeout_on_keeps_the_inner_error() {
+        let clock = VirtualClock::new();
+        let outcome = run(&clock, {
+            let clock = clock.clone();
+            async move {
+                async { Err::<(), _>("refused") }
+                    .try_timeout_on(&clock, Duration::from_secs(5))
+                    .await
+            }
+        });
+
+        assert_eq!(outcome, Err(TimeoutError::Inner("refused")));
+    }
+
+    #[test]
+    fn timeout_each_on_flags_late_items_and_carries_on() {
+        let clock = VirtualClock::new();
+        let items = spaced(&clock, &[1, 3, 1]);
+        let outcome = run(&clock, {
+            let clock = clock.clone();
+            async move {
+                items
+                    .timeout_each_on(&clock, Duration::from_secs(2))
+                    .collect::<Vec<_>>()
+                    .await
+            }
+        });
+
+        let secs = Duration::from_secs;
+        assert_eq!(
+            outcome,
+            [Ok(secs(1)), Err(secs(2)), Ok(secs(3)), Ok(secs(1))]
+        );
+    }
+
+    #[test]
+    fn timeout_total_on_ends_the_stream() {
+        let clock = VirtualClock::new();
+        let items = spaced(&clock, &[1, 1, 1, 1]);
+        let outcome = run(&clock, {
+            let clock = clock.clone();
+            async move {
+                items
+                    .timeout_total_on(&clock, Duration::from_millis(2_500))
+                    .collect::<Vec<_>>()
+                    .await
+            }
+        });
+
+        let secs = Duration::from_secs;
+        assert_eq!(
+            outcome,
+            [Ok(secs(1)), Ok(secs(1)), Err(Duration::from_millis(2_500))]
+        );
+    }
+}
@@ -0,0 +1,217 @@
+use std::{
+    error::Error,
+    fmt::{self, Display},
+    time::Duration,
+};
+
+/// One failed try at the operation.
+#[derive(Debug)]
+pub struct Attempt<E> {
+    /// How long after the first attempt this one began.
+    pub start: Duration,
+
+    /// How long the operation ran before failing.
+    pub duration: Duration,
+
+    /// How long `retry` slept before the next attempt, or `None` if this was
+    /// the last one.
+    pub delay: Option<Duration>,
+
+    /// Why it failed, or `None` if the deadline cut it short.
+    pub error: Option<E>,
+}
+
+/// Why `retry` stopped trying.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    /// The policy's attempts ran out.
+    Attempts,
+
+    /// The policy's deadline passed, or would have before the next attempt.
+    Deadline,
+
+    /// The last error was [`Fault::Permanent`](crate::Fault::Permanent), so
+    /// trying again would not help.
+    Permanent,
+}
+
+/// Every attempt failed, or one failed in a way retrying cannot fix.
+///
+/// Displaying it gives a one-line summary ending with the last error. The
+/// alternate form, `{:#}`, adds a line for every attempt:
+///
+/// ```text
+/// gave up after 3 attempts in 63.4ms: Timed out after 7ms
+///   attempt 1 at 0ns failed after 7.1ms, then waited 10ms: Timed out after 7ms
+///   attempt 2 at 17.3ms failed after 3.2ms, then waited 20ms: Timed out after 3ms
+///   attempt 3 at 40.7ms failed after 9.1ms: Timed out after 9ms
+/// ```
+#[derive(Debug)]
+pub struct GaveUp<E> {
+    /// Every attempt, in order. There is always at least one.
+    pub history: Vec<Attempt<E>>,
+
+    /// The wall time from the start of the first attempt to the end of the
+    /// last, including every delay in between.
+    pub elapsed: Duration,
+
+    pub reason: Reason,
+}
+
+impl<E> GaveUp<E> {
+    /// How many times the operation ran.
+    pub fn attempts(&self) -> usize {
+        self.history.len()
+    }
+
+    /// The most recent error, skipping an attempt the deadline cut short.
+    /// There is none if the deadline cut short the very first attempt.
+    pub fn last_error(&self) -> Option<&E> {
+        self.history
+            .iter()
+            .rev()
+            .find_map(|attempt| attempt.error.as_ref())
+    }
+
+    /// The most recent error, dropping the rest of the history.
+    pub fn into_last_error(self) -> Option<E> {
+        self.history
+            .into_iter()
+            .rev()
+            .find_map(|attempt| attempt.error)
+    }
+}
+
+impl<E: Display> Display for GaveUp<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let attempts = self.attempts();
+        let plural = if attempts == 1 { "" } else { "s" };
+        write!(
+            f,
+            "gave up after {attempts} attempt{plural} in {:.1?}",
+            self.elapsed
+        )?;
+        match self.reason {
+            Reason::Attempts => {}
+            Reason::Deadline => write!(f, " at the deadline")?,
+            Reason::Permanent => write!(f, " on a permanent error")?,
+        }
+        if let Some(error) = self.last_error() {
+            write!(f, ": {error}")?;
+        }
+
+        if f.alternate() {
+            for (number, attempt) in self.history.iter().enumerate() {
+                write!(
+                    f,
+                    "\n  attempt {} at {:.1?} failed after {:.1?}",
+                    number + 1,
+                    attempt.start,
+                    attempt.duration
+                )?;
+                if let Some(delay) = attempt.delay {
+                    write!(f, ", then waited {delay:.1?}")?;
+                }
+                match &attempt.error {
+                    Some(error) => write!(f, ": {error}")?,
+                    None => write!(f, ": cut short by the deadline")?,
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<E: Error + 'static> Error for GaveUp<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.last_error()
+            .map(|error| error as &(dyn Error + 'static))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, num::NonZeroU32, rc::Rc};
+
+    use super::*;
+    use crate::{retry, tests::run, Clock, RetryPolicy, Schedule, VirtualClock};
+
+    #[derive(Debug, PartialEq)]
+    struct Failed(&'static str);
+
+    impl Display for Failed {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{} failed", self.0)
+        }
+    }
+
+    impl Error for Failed {}
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    /// Two attempts which fail after 2s each, then a third which the 10s
+    /// deadline cuts short.
+    fn gave_up() -> GaveUp<Failed> {
+        let clock = VirtualClock::new();
+        let policy = RetryPolicy::new()
+            .max_attempts(NonZeroU32::new(5).unwrap())
+            .base_delay(secs(1))
+            .schedule(Schedule::Exponential)
+            .deadline(secs(10))
+            .clock(clock.clone());
+
+        let op = {
+            let clock = clock.clone();
+            let calls = Rc::new(Cell::new(0));
+            move || {
+                calls.set(calls.get() + 1);
+                let (took, error) = match calls.get() {
+                    1 => (2, Failed("first")),
+                    2 => (2, Failed("second")),
+                    _ => (60, Failed("third")),
+                };
+                let sleep = clock.sleep(secs(took));
+                async move {
+                    sleep.await;
+                    Err::<(), _>(error)
+                }
+            }
+        };
+
+        run(&clock, async move { retry(&policy, op).await }).unwrap_err()
+    }
+
+    #[test]
+    fn alternate_form_lists_every_attempt() {
+        let gave_up = gave_up();
+
+        assert_eq!(
+            format!("{gave_up}"),
+            "gave up after 3 attempts in 10.0s at the deadline: second failed"
+        );
+        assert_eq!(
+            format!("{gave_up:#}"),
+            "gave up after 3 attempts in 10.0s at the deadline: second failed\n  \
+             attempt 1 at 0.0ns failed after 2.0s, then waited 1.0s: first failed\n  \
+             attempt 2 at 3.0s failed after 2.0s, then waited 2.0s: second failed\n  \
+             attempt 3 at 7.0s failed after 3.0s: cut short by the deadline"
+        );
+    }
+
+    #[test]
+    fn last_error_skips_an_attempt_cut_short() {
+        let gave_up = gave_up();
+
+        assert_eq!(gave_up.reason, Reason::Deadline);
+        assert!(gave_up.history[2].error.is_none());
+        assert_eq!(gave_up.last_error(), Some(&Failed("second")));
+
+        let source = gave_up.source().unwrap();
+        assert_eq!(source.downcast_ref(), Some(&Failed("second")));
+
+        assert_eq!(gave_up.into_last_error(), Some(Failed("second")));
+    }
+}
@@ -0,0 +1,401 @@
+use std::{
+    collections::VecDeque,
+    future::Future,
+    num::NonZeroUsize,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures::{
+    future::{self, BoxFuture, Either},
+    stream::{FuturesUnordered, StreamExt},
+};
+
+use crate::{default_clock, Clock};
+
+/// How many latencies a percentile delay needs to have seen before trusting
+/// them over its initial delay.
+const MIN_SAMPLES: usize = 10;
+
+/// How long [`hedge`] waits on an attempt before starting another alongside
+/// it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HedgeDelay {
+    /// Always wait this long.
+    Fixed(Duration),
+
+    /// Wait as long as this fraction of recent successful attempts took, so
+    /// `Percentile(0.95)` starts a second attempt only for the slowest 5% of
+    /// calls.
+    ///
+    /// Only the winner of a hedged call finishes, so on its own its latency
+    /// would make attempts look faster than they are, and the delay would
+    /// creep down. An attempt which had already run longer than the winner
+    /// when it was dropped counts too, as having taken as long as it had run.
+    Percentile(f64),
+}
+
+/// When and how often to hedge, and what latencies it has seen so far.
+///
+/// Cloning a `HedgePolicy` shares its latency history, so every clone learns
+/// from every call.
+#[derive(Debug, Clone)]
+pub struct HedgePolicy {
+    delay: HedgeDelay,
+    initial_delay: Duration,
+    max_attempts: NonZeroUsize,
+    sample_size: usize,
+    latencies: Arc<Mutex<VecDeque<Duration>>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl HedgePolicy {
+    /// Start another attempt whenever `delay` passes with no success yet, up to
+    /// two attempts in all.
+    pub fn fixed(delay: Duration) -> HedgePolicy {
+        HedgePolicy::new(HedgeDelay::Fixed(delay))
+    }
+
+    /// Start another attempt when the current ones have run longer than the
+    /// `percentile` of recent latencies, up to two attempts in all.
+    ///
+    /// # Panics
+    ///
+    /// If `percentile` is not between 0 and 1.
+    pub fn percentile(percentile: f64) -> HedgePolicy {
+        assert!(
+            (0.0..=1.0).contains(&percentile),
+            "hedging percentile must be between 0 and 1, not {percentile}"
+        );
+        HedgePolicy::new(HedgeDelay::Percentile(percentile))
+    }
+
+    fn new(delay: HedgeDelay) -> HedgePolicy {
+        HedgePolicy {
+            delay,
+            initial_delay: Duration::from_millis(100),
+            max_attempts: NonZeroUsize::new(2).unwrap(),
+            sample_size: 100,
+            latencies: Arc::new(Mutex::new(VecDeque::new())),
+            clock: default_clock(),
+        }
+    }
+
+    /// Start at most this many attempts in all, counting the first.
+    pub fn max_attempts(mut self, max_attempts: NonZeroUsize) -> HedgePolicy {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// For a percentile delay, how long to wait until enough latencies have
+    /// come in to go on.
+    pub fn initial_delay(mut self, initial_delay: Duration) -> HedgePolicy {
+        self.initial_delay = initial_delay;
+        self
+    }
+
+    /// For a percentile delay, how many of the most recent latencies to take
+    /// the percentile of.
+    pub fn sample_size(mut self, sample_size: usize) -> HedgePolicy {
+        self.sample_size = sample_size.max(1);
+        self
+    }
+
+    /// Tell the time and sleep with `clock`, instead of the default one.
+    pub fn clock(mut self, clock: impl Clock + 'static) -> HedgePolicy {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// How long to wait before the next hedge, given what has been seen so
+    /// far.
+    pub fn delay(&self) -> Duration {
+        let percentile = match self.delay {
+            HedgeDelay::Fixed(delay) => return delay,
+            HedgeDelay::Percentile(percentile) => percentile,
+        };
+
+        let latencies = self
+            .latencies
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if latencies.len() < MIN_SAMPLES.min(self.sample_size) {
+            return self.initial_delay;
+        }
+
+        let mut sorted: Vec<_> = latencies.iter().copied().collect();
+        sorted.sort_unstable();
+
+        // The nearest-rank percentile.
+        let rank = (percentile * sorted.len() as f64).ceil() as usize;
+        sorted[rank.clamp(1, sorted.len()) - 1]
+    }
+
+    fn record(&self, latency: Duration) {
+        if let HedgeDelay::Fixed(_) = self.delay {
+            return;
+        }
+
+        let mut latencies = self
+            .latencies
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if latencies.len() >= self.sample_size {
+            latencies.pop_front();
+        }
+        latencies.push_back(latency);
+    }
+}
+
+/// Start `op`, and if it has not succeeded after the policy's delay, start it
+/// again alongside, up to the policy's max attempts. An attempt which fails
+/// makes way for the next one straight away. The first success wins, and the
+/// attempts still running are dropped, which cancels them.
+///
+/// Only hedge operations which are safe to run more than once, like reads.
+///
+/// If every attempt fails, hands back the error from the last one to finish.
+pub async fn hedge<F, T, E, Fut>(policy: &HedgePolicy, mut op: F) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let clock = &policy.clock;
+    let numbered = |attempt: usize, future: Fut| async move { (attempt, future.await) };
+
+    // When each attempt started, until it finishes.
+    let mut running_since = vec![Some(clock.now())];
+    let mut running = FuturesUnordered::new();
+    running.push(numbered(0, op()));
+    let mut started = 1;
+    let mut timer = next_timer(policy, started);
+
+    loop {
+        let next = running.next();
+        let finished = match &mut timer {
+            Some(sleep) => match future::select(sleep, next).await {
+                Either::Left(((), _)) => None,
+                Either::Right((finished, _)) => finished,
+            },
+            None => next.await,
+        };
+
+        let exhausted = started >= policy.max_attempts.get();
+        let finished = finished.map(|(attempt, result)| {
+            let since = running_since[attempt].take().expect("attempts finish once");
+            (result, clock.now() - since)
+        });
+
+        match finished {
+            Some((Ok(value), latency)) => {
+                policy.record(latency);
+
+                // The ones still running took at least this long; only those
+                // slower than the winner tell us anything.
+                let now = clock.now();
+                running_since
+                    .iter()
+                    .flatten()
+                    .map(|&since| now - since)
+                    .filter(|&ran| ran > latency)
+                    .for_each(|ran| policy.record(ran));
+                return Ok(value);
+            }
+            Some((Err(error), _)) if exhausted && running.is_empty() => return Err(error),
+            Some((Err(_), _)) if exhausted => continue,
+
+            // Either the timer went off or an attempt failed, and there is
+            // room for another.
+            _ => {}
+        }
+
+        running_since.push(Some(clock.now()));
+        running.push(numbered(started, op()));
+        started += 1;
+        timer = next_timer(policy, started);
+    }
+}
+
+/// A timer for starting the next attempt, if there can be one.
+fn next_timer(policy: &HedgePolicy, started: usize) -> Option<BoxFuture<'static, ()>> {
+    (started < policy.max_attempts.get()).then(|| policy.clock.sleep(policy.delay()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use futures::future::LocalBoxFuture;
+
+    use super::*;
+    use crate::{tests::run, VirtualClock};
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    /// What the attempts of a planned op got up to.
+    #[derive(Debug, Default)]
+    struct Log {
+        /// When each attempt started, in seconds since the clock was made.
+        started: Vec<u64>,
+
+        /// Attempts dropped before they finished.
+        dropped: usize,
+    }
+
+    /// Notes in the log if it is dropped without being defused.
+    struct Fuse(Option<Rc<RefCell<Log>>>);
+
+    impl Fuse {
+        fn defuse(&mut self) {
+            self.0 = None;
+        }
+    }
+
+    impl Drop for Fuse {
+        fn drop(&mut self) {
+            if let Some(log) = self.0.take() {
+                log.borrow_mut().dropped += 1;
+            }
+        }
+    }
+
+    /// An op whose attempts each take as long as the plan says, then return
+    /// what it says, counting from the first attempt.
+    fn planned(
+        clock: &VirtualClock,
+        plan: Vec<(u64, Result<usize, usize>)>,
+    ) -> (
+        impl FnMut() -> LocalBoxFuture<'static, Result<usize, usize>>,
+        Rc<RefCell<Log>>,
+    ) {
+        let log = Rc::new(RefCell::new(Log::default()));
+        let origin = clock.now();
+        let op = {
+            let clock = clock.clone();
+            let log = Rc::clone(&log);
+            move || {
+                let (took, result) = plan[log.borrow().started.len()];
+                log.borrow_mut()
+                    .started
+                    .push((clock.now() - origin).as_secs());
+
+                let mut fuse = Fuse(Some(Rc::clone(&log)));
+                let sleep = clock.sleep(secs(took));
+                Box::pin(async move {
+                    sleep.await;
+                    fuse.defuse();
+                    result
+                }) as LocalBoxFuture<'static, _>
+            }
+        };
+
+        (op, log)
+    }
+
+    fn attempts(max: usize) -> NonZeroUsize {
+        NonZeroUsize::new(max).unwrap()
+    }
+
+    #[test]
+    fn a_second_attempt_starts_after_the_delay() {
+        let clock = VirtualClock::new();
+        let policy = HedgePolicy::fixed(secs(2)).clock(clock.clone());
+        let (op, log) = planned(&clock, vec![(10, Ok(0)), (1, Ok(1))]);
+
+        let result = run(&clock, async move { hedge(&policy, op).await });
+
+        assert_eq!(result, Ok(1));
+        assert_eq!(log.borrow().started, [0, 2]);
+    }
+
+    #[test]
+    fn a_failed_attempt_is_replaced_at_once() {
+        let clock = VirtualClock::new();
+        let policy = HedgePolicy::fixed(secs(5)).clock(clock.clone());
+        let (op, log) = planned(&clock, vec![(1, Err(0)), (1, Ok(1))]);
+
+        let result = run(&clock, async move { hedge(&policy, op).await });
+
+        assert_eq!(result, Ok(1));
+        assert_eq!(log.borrow().started, [0, 1]);
+    }
+
+    #[test]
+    fn max_attempts_is_respected_and_losers_are_dropped() {
+        let clock = VirtualClock::new();
+        let policy = HedgePolicy::fixed(secs(1))
+            .max_attempts(attempts(3))
+            .clock(clock.clone());
+        let (op, log) = planned(
+            &clock,
+            vec![(10, Ok(0)), (10, Ok(1)), (10, Ok(2)), (1, Ok(3))],
+        );
+
+        let result = run(&clock, async move { hedge(&policy, op).await });
+
+        // No fourth attempt, however long the first three take.
+        assert_eq!(result, Ok(0));
+        assert_eq!(log.borrow().started, [0, 1, 2]);
+        assert_eq!(log.borrow().dropped, 2);
+    }
+
+    #[test]
+    fn every_attempt_failing_gives_the_last_error() {
+        let clock = VirtualClock::new();
+        let policy = HedgePolicy::fixed(secs(1))
+            .max_attempts(attempts(3))
+            .clock(clock.clone());
+
+        // They fail at 5s, 2s and 12s, in that order of starting.
+        let (op, log) = planned(&clock, vec![(5, Err(0)), (1, Err(1)), (10, Err(2))]);
+
+        let result = run(&clock, async move { hedge(&policy, op).await });
+
+        assert_eq!(result, Err(2));
+        assert_eq!(log.borrow().started, [0, 1, 2]);
+        assert_eq!(log.borrow().dropped, 0);
+    }
+
+    #[test]
+    fn percentile_delay_takes_over_after_enough_samples() {
+        let clock = VirtualClock::new();
+        let policy = HedgePolicy::percentile(0.5)
+            .initial_delay(secs(60))
+            .clock(clock.clone());
+
+        for took in 1..=MIN_SAMPLES as u64 {
+            assert_eq!(policy.delay(), secs(60));
+
+            let (op, _) = planned(&clock, vec![(took, Ok(0))]);
+            let policy = policy.clone();
+            run(&clock, async move { hedge(&policy, op).await }).unwrap();
+        }
+
+        // The median of 1s to 10s, by nearest rank.
+        assert_eq!(policy.delay(), secs(5));
+    }
+
+    #[test]
+    fn cut_short_attempts_count_as_slow() {
+        let clock = VirtualClock::new();
+        let policy = HedgePolicy::percentile(1.0)
+            .initial_delay(secs(2))
+            .clock(clock.clone());
+        let (op, _) = planned(&clock, vec![(30, Ok(0)), (1, Ok(1))]);
+
+        let result = run(&clock, {
+            let policy = policy.clone();
+            async move { hedge(&policy, op).await }
+        });
+        assert_eq!(result, Ok(1));
+
+        // The winner took 1s, but the first attempt had run for 3s already.
+        let latencies = policy.latencies.lock().unwrap();
+        assert_eq!(
+            Vec::from_iter(latencies.iter().copied()),
+            [secs(1), secs(3)]
+        );
+    }
+}
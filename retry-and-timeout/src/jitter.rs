@@ -0,0 +1,190 @@
+use std::time::Duration;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::RetryPolicy;
+
+/// How much randomness to mix into each delay, so that clients which all
+/// failed at once do not all retry at once too. The strategies are the ones
+/// from the AWS Architecture Blog's "Exponential Backoff And Jitter".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Jitter {
+    /// Wait exactly what the schedule says.
+    None,
+
+    /// Wait anywhere from nothing up to what the schedule says.
+    Full,
+
+    /// Wait at least half of what the schedule says, and at most all of it.
+    Equal,
+
+    /// Wait anywhere from the base delay up to three times the last delay,
+    /// capped at the max delay. This ignores the schedule entirely, since it
+    /// grows on its own.
+    Decorrelated,
+}
+
+impl Jitter {
+    /// The delay to wait when the schedule says `scheduled`, given the policy's
+    /// `base` and `max` delays and the `previous` delay actually waited.
+    pub(crate) fn apply(
+        self,
+        rng: &mut StdRng,
+        scheduled: Duration,
+        base: Duration,
+        previous: Duration,
+        max: Duration,
+    ) -> Duration {
+        match self {
+            Jitter::None => scheduled,
+            Jitter::Full => between(rng, Duration::ZERO, scheduled),
+            Jitter::Equal => between(rng, scheduled / 2, scheduled),
+            Jitter::Decorrelated => {
+                let base = base.min(max);
+                let ceiling = previous.saturating_mul(3).min(max);
+                between(rng, base, ceiling.max(base))
+            }
+        }
+    }
+}
+
+/// The delays one run of [`retry`](crate::retry) waits between attempts, in
+/// order. Get one from [`RetryPolicy::backoff`]; it never runs out, so take
+/// as many as there are retries.
+#[derive(Debug)]
+pub struct Backoff<'p> {
+    policy: &'p RetryPolicy,
+    retry: u32,
+    previous: Duration,
+    rng: StdRng,
+}
+
+impl<'p> Backoff<'p> {
+    pub(crate) fn new(policy: &'p RetryPolicy) -> Backoff<'p> {
+        let rng = match policy.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+
+        Backoff {
+            policy,
+            retry: 0,
+            previous: policy.base_delay,
+            rng,
+        }
+    }
+}
+
+impl Iterator for Backoff<'_> {
+    type Item = Duration;
+
+    fn next(&mut self) -> Option<Duration> {
+        self.retry = self.retry.saturating_add(1);
+        let delay = self.policy.jitter.apply(
+            &mut self.rng,
+            self.policy.delay(self.retry),
+            self.policy.base_delay,
+            self.previous,
+            self.policy.max_delay,
+        );
+
+        self.previous = delay;
+        Some(delay)
+    }
+}
+
+/// A duration picked uniformly from `low..=high`.
+fn between(rng: &mut StdRng, low: Duration, high: Duration) -> Duration {
+    if low >= high {
+        return low;
+    }
+
+    let nanos = rng.gen_range(low.as_nanos()..=high.as_nanos());
+    Duration::new(
+        (nanos / 1_000_000_000) as u64,
+        (nanos % 1_000_000_000) as u32,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+
+    use super::*;
+    use crate::Schedule;
+
+    const BASE: Duration = Duration::from_millis(10);
+    const MAX: Duration = Duration::from_millis(1_000);
+
+    /// Run `jitter` over a spread of scheduled and previous delays, checking
+    /// each pick with `check(scheduled, previous, delay)`.
+    fn sample(jitter: Jitter, check: impl Fn(Duration, Duration, Duration)) {
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut inputs = StdRng::seed_from_u64(7);
+        for _ in 0..10_000 {
+            let scheduled = Duration::from_micros(inputs.gen_range(0..=2_000_000));
+            let previous = Duration::from_micros(inputs.gen_range(0..=2_000_000));
+            let delay = jitter.apply(&mut rng, scheduled, BASE, previous, MAX);
+            check(scheduled, previous, delay);
+        }
+    }
+
+    #[test]
+    fn none_waits_exactly_the_schedule() {
+        sample(Jitter::None, |scheduled, _, delay| {
+            assert_eq!(delay, scheduled);
+        });
+    }
+
+    #[test]
+    fn full_waits_up_to_the_schedule() {
+        sample(Jitter::Full, |scheduled, _, delay| {
+            assert!(delay <= scheduled, "{delay:?} > {scheduled:?}");
+        });
+    }
+
+    #[test]
+    fn equal_waits_at_least_half_the_schedule() {
+        sample(Jitter::Equal, |scheduled, _, delay| {
+            assert!(
+                scheduled / 2 <= delay && delay <= scheduled,
+                "{delay:?} outside {:?}..={scheduled:?}",
+                scheduled / 2
+            );
+        });
+    }
+
+    #[test]
+    fn decorrelated_stays_between_base_and_three_times_previous() {
+        sample(Jitter::Decorrelated, |_, previous, delay| {
+            let high = (previous * 3).min(MAX).max(BASE);
+            assert!(
+                BASE <= delay && delay <= high,
+                "{delay:?} outside {BASE:?}..={high:?}"
+            );
+        });
+    }
+
+    #[test]
+    fn decorrelated_with_base_over_max_waits_max() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let delay = Jitter::Decorrelated.apply(&mut rng, MAX, MAX * 2, MAX, MAX);
+        assert_eq!(delay, MAX);
+    }
+
+    #[test]
+    fn seeded_backoff_repeats() {
+        let policy = RetryPolicy::new()
+            .max_attempts(NonZeroU32::new(9).unwrap())
+            .base_delay(BASE)
+            .max_delay(MAX)
+            .schedule(Schedule::Exponential);
+
+        for jitter in [Jitter::Full, Jitter::Equal, Jitter::Decorrelated] {
+            let seeded = policy.clone().jitter(jitter).seed(42);
+            let first: Vec<_> = seeded.backoff().take(8).collect();
+            let second: Vec<_> = seeded.backoff().take(8).collect();
+            assert_eq!(first, second, "{jitter:?}");
+        }
+    }
+}
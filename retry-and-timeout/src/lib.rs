@@ -0,0 +1,417 @@
+//! Run a future with a time limit, keep trying a fallible operation according
+//! to a [`RetryPolicy`], stop calling one which keeps failing with a
+//! [`CircuitBreaker`], or race a slow one against a fresh attempt with
+//! [`hedge`].
+
+use std::{
+    future::Future,
+    pin::pin,
+    time::{Duration, Instant},
+};
+
+use futures::future;
+
+mod breaker;
+mod clock;
+mod ext;
+mod gave_up;
+mod hedge;
+mod jitter;
+mod policy;
+
+pub use breaker::{BreakerError, CircuitBreaker, State};
+#[cfg(feature = "tokio")]
+pub use clock::TokioClock;
+pub use clock::{default_clock, Clock, ThreadClock, VirtualClock};
+pub use ext::{TimeoutError, TimeoutExt, TimeoutStreamExt};
+pub use gave_up::{Attempt, GaveUp, Reason};
+pub use hedge::{hedge, HedgeDelay, HedgePolicy};
+pub use jitter::{Backoff, Jitter};
+pub use policy::{RetryPolicy, Schedule};
+
+/// Run `future`, giving up on it after `duration`. On timeout, the error is
+/// the duration which ran out.
+pub async fn timeout<F: Future>(duration: Duration, future: F) -> Result<F::Output, Duration> {
+    timeout_on(&default_clock(), duration, future).await
+}
+
+/// Run `future`, giving up on it at `deadline`. On timeout, the error is how
+/// long it had, from when it was first polled.
+pub async fn deadline<F: Future>(deadline: Instant, future: F) -> Result<F::Output, Duration> {
+    deadline_on(&default_clock(), deadline, future).await
+}
+
+/// [`timeout`], timed by `clock`.
+pub async fn timeout_on<C, F>(
+    clock: &C,
+    duration: Duration,
+    future: F,
+) -> Result<F::Output, Duration>
+where
+    C: Clock + ?Sized,
+    F: Future,
+{
+    let timer = clock.sleep(duration);
+    let fut = pin!(future);
+    match future::select(timer, fut).await {
+        future::Either::Left(_) => Err(duration),
+        future::Either::Right((output, _)) => Ok(output),
+    }
+}
+
+/// [`deadline`], timed by `clock`.
+pub async fn deadline_on<C, F>(
+    clock: &C,
+    deadline: Instant,
+    future: F,
+) -> Result<F::Output, Duration>
+where
+    C: Clock + ?Sized,
+    F: Future,
+{
+    let given = deadline.saturating_duration_since(clock.now());
+    let timer = clock.sleep_until(deadline);
+    let fut = pin!(future);
+    match future::select(timer, fut).await {
+        future::Either::Left(_) => Err(given),
+        future::Either::Right((output, _)) => Ok(output),
+    }
+}
+
+/// Call `op` until the future it returns succeeds, as many times as `policy`
+/// allows and waiting as long as it says in between. On success, hands back
+/// the value along with how many attempts it took.
+///
+/// Every error is taken to be worth retrying. Use [`retry_if`] when some can
+/// never succeed.
+pub async fn retry<F, T, E, Fut>(policy: &RetryPolicy, op: F) -> Result<(T, u32), GaveUp<E>>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    retry_if(policy, op, |_| Fault::Transient).await
+}
+
+/// Like [`retry`], but asks `classify` about each error, and gives up straight
+/// away on a [`Fault::Permanent`] one. A [`Fault::RetryAfter`] one waits as
+/// long as it says instead of what the policy would, within the policy's
+/// limits.
+pub async fn retry_if<F, T, E, Fut, C>(
+    policy: &RetryPolicy,
+    mut op: F,
+    mut classify: C,
+) -> Result<(T, u32), GaveUp<E>>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    C: FnMut(&E) -> Fault,
+{
+    let mut backoff = policy.backoff();
+    let mut history = Vec::new();
+    let clock = &policy.clock;
+    let first = clock.now();
+    let deadline = policy.deadline.map(|deadline| first + deadline);
+    let mut attempt = 1;
+    loop {
+        let started = clock.now();
+        let outcome = match deadline {
+            Some(deadline) => deadline_on(clock, deadline, op()).await,
+            None => Ok(op().await),
+        };
+
+        let duration = clock.now() - started;
+
+        let (error, fault) = match outcome {
+            Ok(Ok(value)) => return Ok((value, attempt)),
+            Ok(Err(error)) => {
+                let fault = classify(&error);
+                (Some(error), Some(fault))
+            }
+            Err(_elapsed) => (None, None),
+        };
+
+        let stop = match fault {
+            None => Some(Reason::Deadline),
+            Some(Fault::Permanent) => Some(Reason::Permanent),
+            Some(_) if policy.max_attempts.is_some_and(|max| attempt >= max.get()) => {
+                Some(Reason::Attempts)
+            }
+            Some(_) => None,
+        };
+
+        // Either how long to sleep before the next attempt, or why there will
+        // not be one. There is no point sleeping right up to the deadline.
+        let next = match stop {
+            Some(reason) => Err(reason),
+            None => {
+                let scheduled = backoff.next().unwrap_or_default();
+                let delay = match (fault, deadline) {
+                    // Rather than give up on a hint which runs past the
+                    // deadline, cut it short enough to leave the next attempt
+                    // as long as this one took.
+                    (Some(Fault::RetryAfter(hint)), Some(deadline)) => {
+                        let budget = deadline
+                            .saturating_duration_since(clock.now())
+                            .saturating_sub(duration);
+                        hint.min(policy.max_delay).min(budget)
+                    }
+                    (Some(Fault::RetryAfter(hint)), None) => hint.min(policy.max_delay),
+                    _ => scheduled,
+                };
+                let wakes = clock.now().checked_add(delay);
+                match deadline {
+                    Some(deadline) if wakes.is_none_or(|wakes| wakes >= deadline) => {
+                        Err(Reason::Deadline)
+                    }
+                    _ => Ok(delay),
+                }
+            }
+        };
+
+        history.push(Attempt {
+            start: started - first,
+            duration,
+            delay: next.ok(),
+            error,
+        });
+
+        match next {
+            Ok(delay) => clock.sleep(delay).await,
+            Err(reason) => {
+                return Err(GaveUp {
+                    history,
+                    elapsed: clock.now() - first,
+                    reason,
+                })
+            }
+        }
+
+        attempt = attempt.saturating_add(1);
+    }
+}
+
+/// Whether an error might go away if the operation is tried again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Worth another try: a timeout, a dropped connection, a busy server.
+    Transient,
+
+    /// Will fail the same way every time: bad input, a missing resource, no
+    /// permission.
+    Permanent,
+
+    /// Worth another try, but not for this long, as with an HTTP `Retry-After`
+    /// header or a rate limit which resets at a known time. The wait is capped
+    /// at the policy's max delay, and cut short if it would run so close to
+    /// the deadline that the next attempt would have less time than the last
+    /// one took. `retry` only gives up if there is no time left at all.
+    RetryAfter(Duration),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, num::NonZeroU32, rc::Rc};
+
+    use futures::{executor::LocalPool, task::LocalSpawnExt};
+
+    use super::*;
+
+    /// Run `future` to completion on `clock`, skipping straight to each
+    /// wake-up whenever everything is asleep.
+    pub(crate) fn run<T: 'static>(
+        clock: &VirtualClock,
+        future: impl Future<Output = T> + 'static,
+    ) -> T {
+        let output = Rc::new(Cell::new(None));
+        let mut pool = LocalPool::new();
+        pool.spawner()
+            .spawn_local({
+                let output = Rc::clone(&output);
+                async move { output.set(Some(future.await)) }
+            })
+            .unwrap();
+
+        loop {
+            pool.run_until_stalled();
+            match clock.next_wake() {
+                Some(wake) => clock.advance(wake - clock.now()),
+                None => break,
+            }
+        }
+
+        output.take().expect("the future never finished")
+    }
+
+    #[test]
+    fn retry_after_past_the_deadline_is_cut_short() {
+        let clock = VirtualClock::new();
+        let policy = RetryPolicy::new()
+            .max_attempts(NonZeroU32::new(2).unwrap())
+            .max_delay(Duration::from_secs(60))
+            .deadline(Duration::from_secs(10))
+            .clock(clock.clone());
+
+        let op = {
+            let clock = clock.clone();
+            let calls = Rc::new(Cell::new(0));
+            move || {
+                let clock = clock.clone();
+                let calls = Rc::clone(&calls);
+                async move {
+                    calls.set(calls.get() + 1);
+                    if calls.get() == 1 {
+                        clock.sleep(Duration::from_secs(2)).await;
+                        Err("slow down")
+                    } else {
+                        clock.sleep(Duration::from_secs(1)).await;
+                        Ok("done")
+                    }
+                }
+            }
+        };
+
+        let hint = Fault::RetryAfter(Duration::from_secs(30));
+        let outcome = run(&clock, async move { retry_if(&policy, op, |_| hint).await });
+
+        // The first attempt took 2s, leaving 8s, so the wait is cut to 6s to
+        // leave the second attempt as long again, and it needs only 1s.
+        let (value, attempts) = outcome.unwrap();
+        assert_eq!((value, attempts), ("done", 2));
+    }
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn retry_waits_out_the_schedule_on_the_clock() {
+        let clock = VirtualClock::new();
+        let started = clock.now();
+        let policy = RetryPolicy::new()
+            .max_attempts(NonZeroU32::new(5).unwrap())
+            .base_delay(secs(1))
+            .schedule(Schedule::Exponential)
+            .clock(clock.clone());
+
+        let gave_up = run(&clock, async move {
+            retry(&policy, || async { Err::<(), _>("busy") }).await
+        })
+        .unwrap_err();
+
+        assert_eq!(gave_up.reason, Reason::Attempts);
+        let delays: Vec<_> = gave_up
+            .history
+            .iter()
+            .map(|attempt| attempt.delay)
+            .collect();
+        assert_eq!(
+            delays,
+            [
+                Some(secs(1)),
+                Some(secs(2)),
+                Some(secs(4)),
+                Some(secs(8)),
+                None
+            ]
+        );
+        assert_eq!(gave_up.elapsed, secs(15));
+        assert_eq!(clock.now() - started, secs(15));
+    }
+
+    #[test]
+    fn a_permanent_error_stops_at_once() {
+        let clock = VirtualClock::new();
+        let started = clock.now();
+        let policy = RetryPolicy::new()
+            .max_attempts(NonZeroU32::new(5).unwrap())
+            .base_delay(secs(1))
+            .clock(clock.clone());
+
+        let calls = Rc::new(Cell::new(0));
+        let op = {
+            let calls = Rc::clone(&calls);
+            move || {
+                calls.set(calls.get() + 1);
+                async { Err::<(), _>("not found") }
+            }
+        };
+
+        let gave_up = run(&clock, async move {
+            retry_if(&policy, op, |_| Fault::Permanent).await
+        })
+        .unwrap_err();
+
+        assert_eq!(calls.get(), 1);
+        assert_eq!(gave_up.reason, Reason::Permanent);
+        assert_eq!(gave_up.attempts(), 1);
+        assert_eq!(gave_up.history[0].delay, None);
+        assert_eq!(gave_up.last_error(), Some(&"not found"));
+
+        // Not even the first backoff delay.
+        assert_eq!(gave_up.elapsed, Duration::ZERO);
+        assert_eq!(clock.now(), started);
+    }
+
+    #[test]
+    fn retry_stops_short_of_the_deadline() {
+        let clock = VirtualClock::new();
+        let policy = RetryPolicy::new()
+            .max_attempts(NonZeroU32::new(10).unwrap())
+            .base_delay(secs(1))
+            .schedule(Schedule::Exponential)
+            .deadline(secs(10))
+            .clock(clock.clone());
+
+        let gave_up = run(&clock, async move {
+            retry(&policy, || async { Err::<(), _>("busy") }).await
+        })
+        .unwrap_err();
+
+        // After waiting 1s, 2s and 4s, another 8s would run past the deadline.
+        assert_eq!(gave_up.reason, Reason::Deadline);
+        assert_eq!(gave_up.attempts(), 4);
+        assert_eq!(gave_up.elapsed, secs(7));
+    }
+
+    #[test]
+    fn retry_waits_for_an_open_breaker_to_cool_down() {
+        let clock = VirtualClock::new();
+        let started = clock.now();
+        let policy = RetryPolicy::new()
+            .max_attempts(NonZeroU32::new(5).unwrap())
+            .base_delay(secs(1))
+            .max_delay(secs(60))
+            .schedule(Schedule::Constant)
+            .clock(clock.clone());
+        let breaker = CircuitBreaker::new()
+            .failure_threshold(2)
+            .cool_down(secs(30))
+            .clock(clock.clone());
+
+        let calls = Rc::new(Cell::new(0));
+        let outcome = run(&clock, {
+            let calls = Rc::clone(&calls);
+            async move {
+                let op = || {
+                    let calls = Rc::clone(&calls);
+                    breaker.call(move || async move {
+                        calls.set(calls.get() + 1);
+                        if calls.get() <= 2 {
+                            Err("down")
+                        } else {
+                            Ok("up")
+                        }
+                    })
+                };
+                retry_if(&policy, op, |error| error.fault(|_| Fault::Transient)).await
+            }
+        });
+
+        // Two failures 1s apart open the breaker. The third attempt, 1s
+        // later, fails fast and waits out the other 29s of cool-down, and the
+        // fourth goes through as the probe.
+        assert_eq!(outcome.unwrap(), ("up", 4));
+        assert_eq!(calls.get(), 3);
+        assert_eq!(clock.now() - started, secs(31));
+    }
+}
@@ -0,0 +1,180 @@
+use std::{num::NonZeroU32, sync::Arc, time::Duration};
+
+use crate::{default_clock, Backoff, Clock, Jitter};
+
+/// How the delay between attempts grows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Schedule {
+    /// Always wait the base delay.
+    Constant,
+
+    /// Wait the base delay, then twice it, then three times it, and so on.
+    Linear,
+
+    /// Multiply the delay by the policy's multiplier after every retry.
+    Exponential,
+
+    /// Wait the base delay times 1, 1, 2, 3, 5, 8, …
+    Fibonacci,
+}
+
+/// How many times to try an operation and how long to wait in between, built
+/// up a piece at a time:
+///
+/// ```text
+/// RetryPolicy::new()
+///     .max_attempts(NonZeroU32::new(5).unwrap())
+///     .deadline(Duration::from_secs(2))
+///     .base_delay(Duration::from_millis(50))
+///     .max_delay(Duration::from_secs(2))
+///     .schedule(Schedule::Fibonacci)
+///     .jitter(Jitter::Full)
+/// ```
+///
+/// By default that is three attempts, waiting 100ms and then 200ms, with no
+/// single wait longer than 30s, no jitter, and no deadline.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub(crate) max_attempts: Option<NonZeroU32>,
+    pub(crate) deadline: Option<Duration>,
+    pub(crate) base_delay: Duration,
+    multiplier: f64,
+    pub(crate) max_delay: Duration,
+    schedule: Schedule,
+    pub(crate) jitter: Jitter,
+    pub(crate) seed: Option<u64>,
+    pub(crate) clock: Arc<dyn Clock>,
+}
+
+impl RetryPolicy {
+    pub fn new() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: NonZeroU32::new(3),
+            deadline: None,
+            base_delay: Duration::from_millis(100),
+            multiplier: 2.0,
+            max_delay: Duration::from_secs(30),
+            schedule: Schedule::Exponential,
+            jitter: Jitter::None,
+            seed: None,
+            clock: default_clock(),
+        }
+    }
+
+    /// Try at most this many times in all, counting the first try.
+    pub fn max_attempts(mut self, max_attempts: NonZeroU32) -> RetryPolicy {
+        self.max_attempts = Some(max_attempts);
+        self
+    }
+
+    /// Keep trying for as long as it takes. Pair this with a [`deadline`], or
+    /// it really will try forever.
+    ///
+    /// [`deadline`]: RetryPolicy::deadline
+    pub fn unlimited_attempts(mut self) -> RetryPolicy {
+        self.max_attempts = None;
+        self
+    }
+
+    /// Give up once this long has passed since the first attempt began. An
+    /// attempt still running when it passes is cut short, and no retry starts
+    /// if its delay would run past it.
+    pub fn deadline(mut self, deadline: Duration) -> RetryPolicy {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// The delay before the first retry, which every schedule starts from.
+    pub fn base_delay(mut self, base_delay: Duration) -> RetryPolicy {
+        self.base_delay = base_delay;
+        self
+    }
+
+    /// How much each delay grows over the one before. Only the exponential
+    /// schedule uses this.
+    ///
+    /// # Panics
+    ///
+    /// If `multiplier` is less than 1, or is not finite.
+    pub fn multiplier(mut self, multiplier: f64) -> RetryPolicy {
+        assert!(
+            multiplier.is_finite() && multiplier >= 1.0,
+            "retry multiplier must be finite and at least 1, not {multiplier}"
+        );
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// Never wait longer than this between two attempts, however far along
+    /// the schedule is.
+    pub fn max_delay(mut self, max_delay: Duration) -> RetryPolicy {
+        self.max_delay = max_delay;
+        self
+    }
+
+    pub fn schedule(mut self, schedule: Schedule) -> RetryPolicy {
+        self.schedule = schedule;
+        self
+    }
+
+    pub fn jitter(mut self, jitter: Jitter) -> RetryPolicy {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Seed the random numbers used for jitter, so every run waits the same
+    /// delays. Without a seed, each run gets its own from the OS.
+    pub fn seed(mut self, seed: u64) -> RetryPolicy {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Tell the time and sleep with `clock`, instead of the default one.
+    pub fn clock(mut self, clock: impl Clock + 'static) -> RetryPolicy {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// The delays to wait between attempts, jitter and all, for one run.
+    pub fn backoff(&self) -> Backoff<'_> {
+        Backoff::new(self)
+    }
+
+    /// How long the schedule says to wait before retry number `retry`,
+    /// counting from 1 for the retry after the first failure, before any
+    /// jitter.
+    pub fn delay(&self, retry: u32) -> Duration {
+        let step = retry.max(1) - 1;
+        let factor = match self.schedule {
+            Schedule::Constant => 1.0,
+            Schedule::Linear => f64::from(step) + 1.0,
+            Schedule::Exponential => self.multiplier.powf(f64::from(step)),
+            Schedule::Fibonacci => fibonacci(step),
+        };
+
+        // Work in floating point so long schedules saturate at the cap rather
+        // than overflowing.
+        let seconds = self.base_delay.as_secs_f64() * factor;
+        Duration::try_from_secs_f64(seconds)
+            .unwrap_or(Duration::MAX)
+            .min(self.max_delay)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy::new()
+    }
+}
+
+/// The `n`th Fibonacci number counting from 0, starting 1, 1, 2, 3, 5, …
+fn fibonacci(n: u32) -> f64 {
+    let (mut current, mut next) = (1.0, 1.0);
+    for _ in 0..n {
+        (current, next) = (next, current + next);
+        if current == f64::INFINITY {
+            break;
+        }
+    }
+    current
+}
@@ -0,0 +1,59 @@
+//! A little of everything `trpl` has, to show the same code running on either
+//! backend:
+//!
+//! ```text
+//! cargo run --example tour
+//! cargo run --example tour --no-default-features --features futures-executor
+//! ```
+
+use std::time::{Duration, Instant};
+
+use trpl::Either;
+
+fn main() {
+    let start = Instant::now();
+
+    trpl::block_on(async {
+        let (sender, mut receiver) = trpl::channel();
+
+        let producer = trpl::spawn(async move {
+            for message in ["hi", "from", "the", "other", "task"] {
+                sender.send(message).unwrap();
+                trpl::sleep(Duration::from_millis(50)).await;
+            }
+            "producer done"
+        });
+
+        let consumer = async {
+            while let Some(message) = receiver.recv().await {
+                println!("got '{message}' at {:?}", start.elapsed());
+            }
+            "consumer done"
+        };
+
+        let (consumed, produced) = trpl::join(consumer, producer).await;
+        println!("{consumed}, {produced}");
+
+        let slow = trpl::sleep(Duration::from_millis(200));
+        let fast = async {
+            trpl::sleep(Duration::from_millis(20)).await;
+            "fast"
+        };
+        match trpl::race(slow, fast).await {
+            Either::Left(()) => println!("slow won?!"),
+            Either::Right(winner) => println!("{winner} won the race"),
+        }
+
+        match trpl::timeout(
+            Duration::from_millis(30),
+            trpl::sleep(Duration::from_secs(1)),
+        )
+        .await
+        {
+            Ok(()) => println!("finished in time?!"),
+            Err(waited) => println!("gave up after {waited:?}"),
+        }
+    });
+
+    println!("all done in {:?}", start.elapsed());
+}
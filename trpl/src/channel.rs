@@ -0,0 +1,78 @@
+//! An unbounded channel for sending values between tasks, shaped like
+//! `std::sync::mpsc` rather than like any one runtime's version.
+
+use std::{
+    error::Error,
+    fmt::{self, Debug, Display},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures::{channel::mpsc, Stream, StreamExt};
+
+/// A new channel. Sending never waits, since there is no bound on how many
+/// values can be waiting in it.
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let (sender, receiver) = mpsc::unbounded();
+    (Sender { inner: sender }, Receiver { inner: receiver })
+}
+
+/// The sending half of a channel. Clone it to have more than one sender.
+pub struct Sender<T> {
+    inner: mpsc::UnboundedSender<T>,
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        Sender {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> Sender<T> {
+    /// Send a value, or get it back if the receiver is gone.
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        self.inner
+            .unbounded_send(value)
+            .map_err(|error| SendError(error.into_inner()))
+    }
+}
+
+/// The receiving half of a channel. It is also a `Stream` of the values sent.
+pub struct Receiver<T> {
+    inner: mpsc::UnboundedReceiver<T>,
+}
+
+impl<T> Receiver<T> {
+    /// The next value, or `None` once every sender is gone and nothing is
+    /// left in the channel.
+    pub async fn recv(&mut self) -> Option<T> {
+        self.inner.next().await
+    }
+}
+
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.inner.poll_next_unpin(cx)
+    }
+}
+
+/// The value which could not be sent because the receiver was gone.
+pub struct SendError<T>(pub T);
+
+impl<T> Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SendError(..)")
+    }
+}
+
+impl<T> Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("sending on a channel whose receiver is gone")
+    }
+}
+
+impl<T> Error for SendError<T> {}
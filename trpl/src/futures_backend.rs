@@ -0,0 +1,67 @@
+use std::{
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    sync::OnceLock,
+    task::{Context, Poll},
+    thread::Result as ThreadResult,
+    time::Duration,
+};
+
+use futures::{
+    channel::oneshot,
+    executor::{self, ThreadPool},
+    FutureExt,
+};
+use retry_and_timeout::{Clock, ThreadClock};
+
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    executor::block_on(future)
+}
+
+/// Spawned tasks all share one thread pool, started the first time it is
+/// needed.
+fn pool() -> &'static ThreadPool {
+    static POOL: OnceLock<ThreadPool> = OnceLock::new();
+    POOL.get_or_init(|| ThreadPool::new().expect("could not start the thread pool"))
+}
+
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let (sender, receiver) = oneshot::channel();
+    pool().spawn_ok(async move {
+        let output = AssertUnwindSafe(future).catch_unwind().await;
+
+        // Nobody may be waiting for the output any more; that is fine.
+        let _ = sender.send(output);
+    });
+
+    JoinHandle { receiver }
+}
+
+pub struct JoinHandle<T> {
+    receiver: oneshot::Receiver<ThreadResult<T>>,
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = T;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        match self.receiver.poll_unpin(cx) {
+            Poll::Ready(Ok(Ok(output))) => Poll::Ready(output),
+            Poll::Ready(Ok(Err(payload))) => panic::resume_unwind(payload),
+            Poll::Ready(Err(oneshot::Canceled)) => panic!("task was dropped before finishing"),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// The futures executor has no timer of its own, and a thread per sleep would
+/// be a lot of threads, so every sleep shares `ThreadClock`'s one timer
+/// thread.
+pub async fn sleep(duration: Duration) {
+    ThreadClock.sleep(duration).await
+}
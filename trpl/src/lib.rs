@@ -0,0 +1,215 @@
+//! The async building blocks the examples need, behind one API which does not
+//! care which runtime is underneath. Tokio's types panic when used outside
+//! Tokio's runtime, and the examples were a mix of `#[tokio::main]`,
+//! `futures::executor::block_on`, and hand-rolled executors; with this crate,
+//! the same example code runs on whichever backend the `tokio` or
+//! `futures-executor` cargo feature picks.
+//!
+//! Everything here which is not about running tasks or time (`join`, `race`,
+//! channels) works the same on both, since it never touches the runtime.
+
+#[cfg(all(feature = "tokio", feature = "futures-executor"))]
+compile_error!(
+    "pick one trpl backend: the `tokio` feature or the `futures-executor` one \
+     (with `default-features = false`)"
+);
+
+#[cfg(not(any(feature = "tokio", feature = "futures-executor")))]
+compile_error!("pick a trpl backend: the `tokio` or `futures-executor` feature");
+
+#[cfg(all(feature = "futures-executor", not(feature = "tokio")))]
+mod futures_backend;
+#[cfg(feature = "tokio")]
+mod tokio_backend;
+
+#[cfg(all(feature = "futures-executor", not(feature = "tokio")))]
+use futures_backend as backend;
+#[cfg(feature = "tokio")]
+use tokio_backend as backend;
+
+pub mod channel;
+
+use std::{
+    future::Future,
+    pin::{pin, Pin},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+pub use futures::future::Either;
+
+pub use crate::channel::channel;
+
+/// Run `future` to completion on the current thread, along with anything it
+/// spawns, and return its output. This is the way in: call it from `main`.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    backend::block_on(future)
+}
+
+/// Run `future` as its own task, alongside the one which spawned it. Only
+/// call this from inside `block_on`.
+///
+/// Awaiting the handle gets the task's output, and a panic in the task comes
+/// back out as a panic there. Dropping the handle lets the task carry on
+/// without anyone waiting for it.
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    JoinHandle {
+        inner: backend::spawn(future),
+    }
+}
+
+/// The eventual output of a task started with `spawn`.
+pub struct JoinHandle<T> {
+    inner: backend::JoinHandle<T>,
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = T;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        Pin::new(&mut self.inner).poll(cx)
+    }
+}
+
+/// Wait for `duration` without blocking the thread.
+pub async fn sleep(duration: Duration) {
+    backend::sleep(duration).await
+}
+
+/// Run `future`, but give up on it if it takes longer than `duration`, in
+/// which case the error is how long we waited.
+pub async fn timeout<F: Future>(duration: Duration, future: F) -> Result<F::Output, Duration> {
+    match race(future, sleep(duration)).await {
+        Either::Left(output) => Ok(output),
+        Either::Right(()) => Err(duration),
+    }
+}
+
+/// Run both futures at once, and wait for both to finish.
+pub async fn join<A: Future, B: Future>(a: A, b: B) -> (A::Output, B::Output) {
+    futures::future::join(a, b).await
+}
+
+/// Run all the futures at once, and wait for all of them to finish. The
+/// outputs are in the same order as the futures.
+pub async fn join_all<I>(futures: I) -> Vec<<I::Item as Future>::Output>
+where
+    I: IntoIterator,
+    I::Item: Future,
+{
+    futures::future::join_all(futures).await
+}
+
+/// Run both futures at once, and take the output of whichever finishes first.
+/// The other one is dropped. If both are ready on the same poll, `a` wins.
+pub async fn race<A: Future, B: Future>(a: A, b: B) -> Either<A::Output, B::Output> {
+    let (a, b) = (pin!(a), pin!(b));
+    match futures::future::select(a, b).await {
+        Either::Left((output, _)) => Either::Left(output),
+        Either::Right((output, _)) => Either::Right(output),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        panic::{self, AssertUnwindSafe},
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::Instant,
+    };
+
+    use super::*;
+
+    fn millis(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn sleep_waits() {
+        let started = Instant::now();
+        block_on(sleep(millis(20)));
+        assert!(started.elapsed() >= millis(20));
+    }
+
+    #[test]
+    fn timeout_gives_up_on_slow_futures_only() {
+        block_on(async {
+            assert_eq!(
+                timeout(millis(10), sleep(Duration::from_secs(5))).await,
+                Err(millis(10))
+            );
+            assert_eq!(timeout(Duration::from_secs(5), async { 5 }).await, Ok(5));
+        });
+    }
+
+    /// Sets its flag when dropped.
+    struct Dropped(Arc<AtomicBool>);
+
+    impl Drop for Dropped {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn race_takes_the_first_and_drops_the_other() {
+        let dropped = Arc::new(AtomicBool::new(false));
+        let slow = {
+            let dropped = Dropped(Arc::clone(&dropped));
+            async move {
+                sleep(Duration::from_secs(5)).await;
+                drop(dropped);
+                "slow"
+            }
+        };
+        let fast = async {
+            sleep(millis(1)).await;
+            "fast"
+        };
+
+        let started = Instant::now();
+        let winner = block_on(race(slow, fast));
+
+        assert!(matches!(winner, Either::Right("fast")));
+        assert!(dropped.load(Ordering::SeqCst));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn spawned_tasks_hand_back_their_output() {
+        let output = block_on(async { spawn(async { 1 + 1 }).await });
+        assert_eq!(output, 2);
+    }
+
+    #[test]
+    fn a_panic_in_a_task_comes_out_of_its_handle() {
+        let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+            block_on(async { spawn(async { panic!("boom") }).await })
+        }));
+
+        let payload = outcome.unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"boom"));
+    }
+
+    #[test]
+    fn channels_close_when_either_end_goes() {
+        block_on(async {
+            let (sender, mut receiver) = channel();
+            sender.send(1).unwrap();
+            drop(sender);
+            assert_eq!(receiver.recv().await, Some(1));
+            assert_eq!(receiver.recv().await, None);
+
+            let (sender, receiver) = channel();
+            drop(receiver);
+            assert_eq!(sender.send(2).unwrap_err().0, 2);
+        });
+    }
+}
@@ -0,0 +1,46 @@
+use std::{
+    future::Future,
+    panic,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use tokio::{runtime::Runtime, task};
+
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    Runtime::new()
+        .expect("could not start the Tokio runtime")
+        .block_on(future)
+}
+
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    JoinHandle {
+        inner: tokio::spawn(future),
+    }
+}
+
+pub async fn sleep(duration: Duration) {
+    tokio::time::sleep(duration).await
+}
+
+pub struct JoinHandle<T> {
+    inner: task::JoinHandle<T>,
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = T;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        match Pin::new(&mut self.inner).poll(cx) {
+            Poll::Ready(Ok(output)) => Poll::Ready(output),
+            Poll::Ready(Err(error)) if error.is_panic() => panic::resume_unwind(error.into_panic()),
+            Poll::Ready(Err(error)) => panic!("task did not finish: {error}"),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}